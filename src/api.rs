@@ -0,0 +1,954 @@
+use crate::logging::request_id;
+use crate::openapi;
+use crate::snippet::{self, SnippetFormat, SnippetTheme};
+use crate::utils::{NOT_READY_RETRY_AFTER, get_cdn_url, get_mirror_urls, parse_datetime};
+use crate::version_checker::{VersionChecker, VersionInfo, is_valid_version};
+use crate::version_range::{Version, VersionRange};
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{
+    ALLOW, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, HeaderValue, LINK, RETRY_AFTER,
+};
+use actix_web::http::{Method, StatusCode};
+use actix_web::middleware::Next;
+use actix_web::{Error, HttpRequest, HttpResponse, Responder, Scope, get, web};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+/// Stable prefix of the JSON API. The same endpoints are also served
+/// directly under `/api/` for clients written before it existed.
+const API_V1_PREFIX: &str = "/api/v1";
+
+const API_PATH: &str = "/api";
+
+/// Longest error text taken over from a handler or extractor
+const MAX_ERROR_MESSAGE_LENGTH: usize = 200;
+
+/// A highlight-it release and where to load it from
+#[derive(Serialize, ToSchema)]
+pub struct VersionResponse {
+    /// Version number
+    #[schema(example = "0.3.2")]
+    pub version: String,
+    /// Subresource Integrity hash of the minified script
+    #[schema(example = "sha512-hD5Y...")]
+    pub sri_hash: String,
+    /// jsDelivr URL of the minified script
+    #[schema(example = "https://cdn.jsdelivr.net/npm/highlight-it@0.3.2/dist/highlight-it-min.js")]
+    pub url: String,
+}
+
+impl From<VersionInfo> for VersionResponse {
+    fn from(info: VersionInfo) -> Self {
+        Self {
+            url: get_cdn_url(&info.version),
+            version: info.version,
+            sri_hash: info.sri_hash,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct VersionsResponse {
+    /// Cached versions matching the filters, newest first
+    versions: Vec<VersionResponse>,
+    /// Newest version published on npm
+    latest: VersionResponse,
+    /// `cursor` of the next page, also sent in the `Link` header
+    #[schema(example = "0.2.1")]
+    next_cursor: Option<String>,
+}
+
+/// Everything known about one version
+#[derive(Serialize, ToSchema)]
+pub struct VersionDetailResponse {
+    /// Version number
+    #[schema(example = "0.3.2")]
+    version: String,
+    /// Subresource Integrity hash of the minified script, valid for every URL
+    #[schema(example = "sha512-hD5Y...")]
+    sri_hash: String,
+    /// jsDelivr URL of the minified script
+    url: String,
+    /// Other CDN URLs of the same script
+    mirrors: Vec<String>,
+    /// Publish time on npm as RFC 3339, if known
+    #[schema(example = "2025-03-01T12:34:56.789Z")]
+    published: Option<String>,
+    /// npm dist-tags pointing at this version
+    #[schema(example = json!(["latest"]))]
+    dist_tags: Vec<String>,
+    /// Whether the version is deprecated on npm
+    deprecated: bool,
+    /// The npm deprecation notice
+    deprecation_message: Option<String>,
+    /// Whether the demo page loads this version by default
+    is_default: bool,
+}
+
+/// Body of every API error response
+#[derive(Serialize, ToSchema)]
+pub struct ApiError {
+    /// Machine-readable error kind
+    #[schema(example = "not_found")]
+    code: &'static str,
+    /// Human-readable description
+    #[schema(example = "Version 9.9.9 is not available")]
+    message: String,
+    /// ID of the request, also sent in `X-Request-Id`
+    #[schema(example = "5e22aab2a06b-00000001")]
+    request_id: String,
+}
+
+/// Error returned when no cached version satisfies a range
+#[derive(Serialize, ToSchema)]
+pub struct ResolveError {
+    #[serde(flatten)]
+    error: ApiError,
+    /// Cached versions nearest to the range, closest first
+    candidates: Vec<VersionResponse>,
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct ResolveQuery {
+    /// npm version range such as `^0.3`, `~1.2.0 || >=2` or a dist-tag
+    #[param(example = "^0.3")]
+    range: String,
+    /// Let prereleases satisfy the range
+    #[serde(default)]
+    include_prerelease: bool,
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct SnippetQuery {
+    /// Version to load, the demo's default version if omitted
+    #[param(example = "0.3.2")]
+    version: Option<String>,
+    /// Stylesheet to include, one of the styles shipped in `dist/styles`
+    #[param(example = "github")]
+    style: Option<String>,
+    /// Shape of the snippet
+    #[serde(default)]
+    #[param(inline)]
+    format: SnippetFormat,
+    /// Theme passed to `HighlightIt.init`
+    #[serde(default)]
+    #[param(inline)]
+    theme: SnippetTheme,
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct VersionsQuery {
+    /// Most versions per page, 1 to 100
+    #[param(example = 20, minimum = 1, maximum = 100)]
+    limit: Option<usize>,
+    /// Continue after this version, taken from `next_cursor`
+    #[param(example = "0.2.1")]
+    cursor: Option<String>,
+    /// Only versions published at or after this RFC 3339 time or date
+    #[param(example = "2025-01-01")]
+    since: Option<String>,
+    /// Only versions published before this RFC 3339 time or date
+    #[param(example = "2026-01-01T00:00:00Z")]
+    until: Option<String>,
+    /// Only versions of this major line
+    #[param(example = 1)]
+    major: Option<u64>,
+    /// Only versions of this minor line, requires `major`
+    #[param(example = 2)]
+    minor: Option<u64>,
+    /// Include prereleases such as 1.3.0-beta.1
+    #[serde(default = "include_prereleases_by_default")]
+    include_prerelease: bool,
+}
+
+fn include_prereleases_by_default() -> bool {
+    true
+}
+
+/// Page size of `/versions` when no `limit` is given, and the largest allowed
+const MAX_VERSIONS_PAGE_SIZE: usize = 100;
+
+/// Most candidates suggested when a range cannot be resolved
+const MAX_CANDIDATES: usize = 5;
+
+/// Whether `path` is `/api` or below it, where every response is JSON.
+pub fn is_api_path(path: &str) -> bool {
+    path.strip_prefix(API_PATH)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+fn error_code(status: StatusCode) -> &'static str {
+    match status {
+        StatusCode::BAD_REQUEST => "bad_request",
+        StatusCode::UNAUTHORIZED => "unauthorized",
+        StatusCode::FORBIDDEN => "forbidden",
+        StatusCode::NOT_FOUND => "not_found",
+        StatusCode::METHOD_NOT_ALLOWED => "method_not_allowed",
+        StatusCode::NOT_ACCEPTABLE => "not_acceptable",
+        StatusCode::TOO_MANY_REQUESTS => "rate_limited",
+        StatusCode::BAD_GATEWAY => "upstream_error",
+        StatusCode::SERVICE_UNAVAILABLE => "unavailable",
+        status if status.is_server_error() => "internal_error",
+        _ => "error",
+    }
+}
+
+fn api_error(req: &HttpRequest, status: StatusCode, message: &str) -> ApiError {
+    ApiError {
+        code: error_code(status),
+        message: message.to_string(),
+        request_id: request_id(req),
+    }
+}
+
+/// A JSON error response for an API request.
+pub fn error_response(req: &HttpRequest, status: StatusCode, message: &str) -> HttpResponse {
+    HttpResponse::build(status).json(api_error(req, status, message))
+}
+
+/// The JSON counterpart of `create_not_ready_response`.
+fn not_ready_response(req: &HttpRequest) -> HttpResponse {
+    HttpResponse::ServiceUnavailable()
+        .append_header((RETRY_AFTER, NOT_READY_RETRY_AFTER))
+        .append_header(("Cache-Control", "no-store"))
+        .json(api_error(
+            req,
+            StatusCode::SERVICE_UNAVAILABLE,
+            "No version is available yet",
+        ))
+}
+
+/// Middleware turning error responses under `/api` that are not JSON yet,
+/// such as extractor failures and unmatched methods, into an `ApiError`.
+/// The original text becomes the message.
+pub async fn json_errors(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let is_api = is_api_path(req.path());
+    let response = next.call(req).await?;
+
+    let status = response.status();
+    let is_json = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+
+    if !is_api || is_json || !(status.is_client_error() || status.is_server_error()) {
+        return Ok(response.map_into_left_body());
+    }
+
+    let (req, response) = response.into_parts();
+    let headers = response.headers().clone();
+    let body = actix_web::body::to_bytes(response.into_body())
+        .await
+        .unwrap_or_default();
+
+    let text = String::from_utf8_lossy(&body);
+    let text = text.trim();
+    let message = if text.is_empty() || text.len() > MAX_ERROR_MESSAGE_LENGTH {
+        status.canonical_reason().unwrap_or("Error")
+    } else {
+        text
+    };
+
+    let mut error = error_response(&req, status, message);
+    for (name, value) in &headers {
+        if name != CONTENT_TYPE && name != CONTENT_LENGTH && name != CONTENT_ENCODING {
+            error.headers_mut().append(name.clone(), value.clone());
+        }
+    }
+
+    Ok(ServiceResponse::new(req, error).map_into_right_body())
+}
+
+/// Answers paths without an endpoint. The API is read-only, so other
+/// methods are refused as such on any path.
+async fn not_found(req: HttpRequest) -> HttpResponse {
+    if req.method() != Method::GET && req.method() != Method::HEAD {
+        let mut response = error_response(
+            &req,
+            StatusCode::METHOD_NOT_ALLOWED,
+            &format!("The API only accepts GET requests, not {}", req.method()),
+        );
+        response
+            .headers_mut()
+            .insert(ALLOW, HeaderValue::from_static("GET, HEAD"));
+        return response;
+    }
+
+    error_response(
+        &req,
+        StatusCode::NOT_FOUND,
+        &format!("No API endpoint at {}", req.path()),
+    )
+}
+
+/// The version the demo page currently loads
+#[utoipa::path(
+    context_path = "/api/v1",
+    tag = "versions",
+    responses(
+        (status = 200, description = "The default version", body = VersionResponse),
+        (status = 503, description = "No version is available yet", body = ApiError,
+            headers(("Retry-After" = u64, description = "Seconds to wait before retrying")))
+    )
+)]
+#[get("/latest")]
+async fn serve_latest_version_api(
+    req: HttpRequest,
+    data: web::Data<VersionChecker>,
+) -> impl Responder {
+    let version_info = data.get_current_version_info().await;
+
+    if version_info.version.is_empty() {
+        return not_ready_response(&req);
+    }
+
+    HttpResponse::Ok()
+        .append_header(("Cache-Control", "public, max-age=60"))
+        .json(VersionResponse::from(version_info))
+}
+
+/// Cached versions, newest first, a page at a time
+#[utoipa::path(
+    context_path = "/api/v1",
+    tag = "versions",
+    params(VersionsQuery),
+    responses(
+        (status = 200, description = "A page of cached versions", body = VersionsResponse,
+            headers(("Link" = String, description = "URL of the next page with rel=\"next\""))),
+        (status = 400, description = "Invalid filter or cursor", body = ApiError)
+    )
+)]
+#[get("/versions")]
+async fn serve_all_versions_api(
+    req: HttpRequest,
+    query: web::Query<VersionsQuery>,
+    data: web::Data<VersionChecker>,
+) -> impl Responder {
+    let query = query.into_inner();
+
+    let limit = query.limit.unwrap_or(MAX_VERSIONS_PAGE_SIZE);
+    if !(1..=MAX_VERSIONS_PAGE_SIZE).contains(&limit) {
+        return error_response(
+            &req,
+            StatusCode::BAD_REQUEST,
+            &format!("limit must be between 1 and {}", MAX_VERSIONS_PAGE_SIZE),
+        );
+    }
+
+    if query.minor.is_some() && query.major.is_none() {
+        return error_response(&req, StatusCode::BAD_REQUEST, "minor requires major");
+    }
+
+    let cursor = match query.cursor.as_deref().map(Version::parse) {
+        Some(None) => {
+            return error_response(&req, StatusCode::BAD_REQUEST, "Invalid cursor");
+        }
+        Some(cursor) => cursor,
+        None => None,
+    };
+
+    let parse_bound = |name: &str, value: &Option<String>| match value {
+        Some(value) => parse_datetime(value)
+            .map(Some)
+            .ok_or_else(|| format!("{} must be an RFC 3339 time or a date", name)),
+        None => Ok(None),
+    };
+    let (since, until) = match (
+        parse_bound("since", &query.since),
+        parse_bound("until", &query.until),
+    ) {
+        (Ok(since), Ok(until)) => (since, until),
+        (Err(message), _) | (_, Err(message)) => {
+            return error_response(&req, StatusCode::BAD_REQUEST, &message);
+        }
+    };
+
+    let mut versions: Vec<(Version, VersionInfo)> = data
+        .get_all_versions()
+        .await
+        .into_iter()
+        .filter_map(|v| Some((Version::parse(&v.version)?, v)))
+        .filter(|(version, _)| {
+            query.major.is_none_or(|major| version.major == major)
+                && query.minor.is_none_or(|minor| version.minor == minor)
+                && (query.include_prerelease || !version.is_prerelease())
+                && cursor.as_ref().is_none_or(|cursor| version < cursor)
+        })
+        .filter(|(_, version_info)| {
+            if since.is_none() && until.is_none() {
+                return true;
+            }
+            // Versions without a known publish date match no date range
+            version_info
+                .published
+                .as_deref()
+                .and_then(parse_datetime)
+                .is_some_and(|published| {
+                    since.is_none_or(|since| published >= since)
+                        && until.is_none_or(|until| published < until)
+                })
+        })
+        .collect();
+    versions.sort_by(|(a, _), (b, _)| b.cmp(a));
+
+    let has_more = versions.len() > limit;
+    versions.truncate(limit);
+    let next_cursor = has_more
+        .then(|| versions.last().map(|(_, v)| v.version.clone()))
+        .flatten();
+
+    let mut response = HttpResponse::Ok();
+    response.append_header(("Cache-Control", "public, max-age=60"));
+
+    if let Some(next_cursor) = &next_cursor {
+        let mut next_query: Vec<&str> = req
+            .query_string()
+            .split('&')
+            .filter(|pair| !pair.is_empty() && !pair.starts_with("cursor="))
+            .collect();
+        let cursor_pair = format!("cursor={}", next_cursor);
+        next_query.push(&cursor_pair);
+
+        response.append_header((
+            LINK,
+            format!("<{}?{}>; rel=\"next\"", req.path(), next_query.join("&")),
+        ));
+    }
+
+    response.json(VersionsResponse {
+        versions: versions
+            .into_iter()
+            .map(|(_, version_info)| VersionResponse::from(version_info))
+            .collect(),
+        latest: VersionResponse::from(data.get_latest_version_info().await),
+        next_cursor,
+    })
+}
+
+/// Everything known about a cached version, as served by the API and by
+/// `/{version}` to clients asking for JSON.
+pub async fn version_detail(
+    data: &VersionChecker,
+    version_info: VersionInfo,
+) -> VersionDetailResponse {
+    let version = version_info.version;
+    let dist_tags = data
+        .get_dist_tags()
+        .await
+        .into_iter()
+        .filter(|(_, tagged)| *tagged == version)
+        .map(|(tag, _)| tag)
+        .collect();
+    let is_default = data.get_current_version_info().await.version == version;
+
+    VersionDetailResponse {
+        url: get_cdn_url(&version),
+        mirrors: get_mirror_urls(&version),
+        sri_hash: version_info.sri_hash,
+        published: version_info.published,
+        dist_tags,
+        deprecated: version_info.deprecated.is_some(),
+        deprecation_message: version_info.deprecated,
+        is_default,
+        version,
+    }
+}
+
+/// Details of one cached version
+#[utoipa::path(
+    context_path = "/api/v1",
+    tag = "versions",
+    params(("version" = String, Path, description = "Version number", example = "0.3.2")),
+    responses(
+        (status = 200, description = "The version", body = VersionDetailResponse),
+        (status = 400, description = "Not a version number", body = ApiError),
+        (status = 404, description = "The version is not cached", body = ApiError)
+    )
+)]
+#[get("/versions/{version}")]
+async fn serve_version_api(
+    req: HttpRequest,
+    path: web::Path<String>,
+    data: web::Data<VersionChecker>,
+) -> impl Responder {
+    let version = path.into_inner();
+
+    if !is_valid_version(&version) {
+        return error_response(
+            &req,
+            StatusCode::BAD_REQUEST,
+            "Expected a version number such as 0.3.2",
+        );
+    }
+
+    let Some(version_info) = data
+        .get_all_versions()
+        .await
+        .into_iter()
+        .find(|v| v.version == version)
+    else {
+        return error_response(
+            &req,
+            StatusCode::NOT_FOUND,
+            &format!("Version {} is not available", version),
+        );
+    };
+
+    HttpResponse::Ok()
+        .append_header(("Cache-Control", "public, max-age=60"))
+        .json(version_detail(&data, version_info).await)
+}
+
+/// Newest cached version satisfying an npm version range
+#[utoipa::path(
+    context_path = "/api/v1",
+    tag = "versions",
+    params(ResolveQuery),
+    responses(
+        (status = 200, description = "The newest matching version", body = VersionResponse),
+        (status = 400, description = "The range cannot be parsed", body = ApiError),
+        (status = 404, description = "No cached version matches", body = ResolveError)
+    )
+)]
+#[get("/resolve")]
+async fn serve_resolve_api(
+    req: HttpRequest,
+    query: web::Query<ResolveQuery>,
+    data: web::Data<VersionChecker>,
+) -> impl Responder {
+    let query = query.into_inner();
+    let dist_tags = data.get_dist_tags().await;
+
+    let range = match dist_tags.get(query.range.trim()) {
+        Some(version) => VersionRange::parse(version),
+        None => VersionRange::parse(&query.range),
+    };
+    let range = match range {
+        Ok(range) => range,
+        Err(e) => return error_response(&req, StatusCode::BAD_REQUEST, &e),
+    };
+
+    let mut versions: Vec<(Version, VersionInfo)> = data
+        .get_all_versions()
+        .await
+        .into_iter()
+        .filter(|v| !v.sri_hash.is_empty())
+        .filter_map(|v| Some((Version::parse(&v.version)?, v)))
+        .collect();
+    versions.sort_by(|(a, _), (b, _)| b.cmp(a));
+
+    if let Some((_, version_info)) = versions
+        .iter()
+        .find(|(version, _)| range.matches(version, query.include_prerelease))
+    {
+        return HttpResponse::Ok()
+            .append_header(("Cache-Control", "public, max-age=60"))
+            .json(VersionResponse::from(version_info.clone()));
+    }
+
+    versions.sort_by_key(|(version, _)| range.distance(version));
+    let status = StatusCode::NOT_FOUND;
+
+    HttpResponse::build(status)
+        .append_header(("Cache-Control", "public, max-age=60"))
+        .json(ResolveError {
+            error: api_error(
+                &req,
+                status,
+                &format!("No available version satisfies {}", query.range),
+            ),
+            candidates: versions
+                .into_iter()
+                .filter(|(version, _)| query.include_prerelease || !version.is_prerelease())
+                .take(MAX_CANDIDATES)
+                .map(|(_, version_info)| VersionResponse::from(version_info))
+                .collect(),
+        })
+}
+
+/// Code to embed a version of highlight-it in a page, with its SRI hash
+#[utoipa::path(
+    context_path = "/api/v1",
+    tag = "versions",
+    params(SnippetQuery),
+    responses(
+        (status = 200, description = "The snippet", body = String, content_type = "text/plain"),
+        (status = 400, description = "Invalid version, style or format", body = ApiError),
+        (status = 404, description = "The version is not cached", body = ApiError),
+        (status = 502, description = "The files to pin could not be downloaded", body = ApiError),
+        (status = 503, description = "No version is available yet", body = ApiError,
+            headers(("Retry-After" = u64, description = "Seconds to wait before retrying")))
+    )
+)]
+#[get("/snippet")]
+async fn serve_snippet_api(
+    req: HttpRequest,
+    query: web::Query<SnippetQuery>,
+    data: web::Data<VersionChecker>,
+) -> impl Responder {
+    let query = query.into_inner();
+
+    let style = query.style.as_deref().unwrap_or("default");
+    if !snippet::is_known_style(style) {
+        return error_response(
+            &req,
+            StatusCode::BAD_REQUEST,
+            &format!(
+                "Unknown style {}, expected one of: {}",
+                style,
+                snippet::STYLES.join(", ")
+            ),
+        );
+    }
+
+    // Snippets for an explicit version never change, the default one moves
+    // with new releases
+    let (version_info, cache_control) = match query.version {
+        Some(version) => {
+            if !is_valid_version(&version) {
+                return error_response(
+                    &req,
+                    StatusCode::BAD_REQUEST,
+                    "Expected a version number such as 0.3.2",
+                );
+            }
+
+            match data
+                .get_all_versions()
+                .await
+                .into_iter()
+                .find(|v| v.version == version && !v.sri_hash.is_empty())
+            {
+                Some(version_info) => (version_info, "public, max-age=86400"),
+                None => {
+                    return error_response(
+                        &req,
+                        StatusCode::NOT_FOUND,
+                        &format!("Version {} is not available", version),
+                    );
+                }
+            }
+        }
+        None => {
+            let version_info = data.get_current_version_info().await;
+            if version_info.version.is_empty() || version_info.sri_hash.is_empty() {
+                return not_ready_response(&req);
+            }
+            (version_info, "public, max-age=60")
+        }
+    };
+
+    let version = version_info.version;
+    let script_url = snippet::script_url(query.format, &version);
+    let script_sri_hash = match query.format {
+        SnippetFormat::Importmap => data.get_file_sri_hash(&script_url).await,
+        _ => Ok(version_info.sri_hash),
+    };
+    let stylesheet_sri_hash = match snippet::style_url(&version, style) {
+        Some(url) => data
+            .get_file_sri_hash(&url)
+            .await
+            .map(|sri_hash| Some(snippet::Pinned { url, sri_hash })),
+        None => Ok(None),
+    };
+    let (Ok(script_sri_hash), Ok(stylesheet)) = (script_sri_hash, stylesheet_sri_hash) else {
+        return error_response(
+            &req,
+            StatusCode::BAD_GATEWAY,
+            "Could not download the files to pin from the CDN",
+        );
+    };
+    let embed = snippet::Embed {
+        script: snippet::Pinned {
+            url: script_url,
+            sri_hash: script_sri_hash,
+        },
+        stylesheet,
+    };
+
+    HttpResponse::Ok()
+        .content_type(query.format.content_type())
+        .append_header(("Cache-Control", cache_control))
+        .body(snippet::render(query.format, &embed, query.theme))
+}
+
+/// The endpoints of API version 1
+fn v1_routes(scope: Scope) -> Scope {
+    scope
+        .service(serve_latest_version_api)
+        .service(serve_all_versions_api)
+        .service(serve_version_api)
+        .service(serve_resolve_api)
+        .service(serve_snippet_api)
+        .default_service(web::to(not_found))
+}
+
+/// Registers the API under `/api/v1` and its aliases under `/api`. The
+/// versioned scope comes first so `/api` does not swallow its paths.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(v1_routes(web::scope(API_V1_PREFIX))).service(
+        v1_routes(web::scope(API_PATH))
+            .service(openapi::serve_openapi)
+            .service(openapi::serve_api_docs),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::version_checker::sample_versions;
+    use actix_web::test::{TestRequest, call_service, init_service, read_body, read_body_json};
+    use actix_web::{App, middleware};
+    use serde_json::Value;
+
+    #[test]
+    fn recognizes_api_paths() {
+        for path in ["/api", "/api/", "/api/v1", "/api/v1/latest", "/api/docs"] {
+            assert!(is_api_path(path), "{}", path);
+        }
+        for path in ["/", "/apis", "/api-docs", "/static/api/x", "/1.2.3"] {
+            assert!(!is_api_path(path), "{}", path);
+        }
+    }
+
+    #[actix_web::test]
+    async fn answers_unknown_api_paths_with_json() {
+        let checker = VersionChecker::with_versions(sample_versions(), &[]).await;
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(checker))
+                .wrap(middleware::from_fn(json_errors))
+                .configure(configure)
+                .default_service(web::to(|| async {
+                    HttpResponse::NotFound()
+                        .content_type("text/html")
+                        .body("<h1>Not found</h1>")
+                })),
+        )
+        .await;
+
+        for (method, uri, status) in [
+            (Method::GET, "/api", StatusCode::NOT_FOUND),
+            (Method::GET, "/api/", StatusCode::NOT_FOUND),
+            (Method::GET, "/api/v1", StatusCode::NOT_FOUND),
+            (Method::GET, "/api/v2/latest", StatusCode::NOT_FOUND),
+            (Method::POST, "/api", StatusCode::METHOD_NOT_ALLOWED),
+            (
+                Method::POST,
+                "/api/v1/latest",
+                StatusCode::METHOD_NOT_ALLOWED,
+            ),
+            (Method::GET, "/api/v1/resolve", StatusCode::BAD_REQUEST),
+            (
+                Method::GET,
+                "/api/v1/snippet?format=systemjs",
+                StatusCode::BAD_REQUEST,
+            ),
+        ] {
+            let req = TestRequest::default().method(method).uri(uri).to_request();
+            let response = call_service(&app, req).await;
+            assert_eq!(response.status(), status, "{}", uri);
+
+            let body: Value = read_body_json(response).await;
+            assert_eq!(body["code"], error_code(status), "{}", uri);
+        }
+
+        let response = call_service(&app, TestRequest::get().uri("/apis").to_request()).await;
+        assert_eq!(response.headers().get(CONTENT_TYPE).unwrap(), "text/html",);
+    }
+
+    #[actix_web::test]
+    async fn filters_and_pages_versions() {
+        let checker = VersionChecker::with_versions(sample_versions(), &[]).await;
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(checker))
+                .configure(configure),
+        )
+        .await;
+
+        for (query, expected) in [
+            (
+                "",
+                &["1.3.0-beta.1", "1.2.3", "1.2.2", "1.1.0", "0.3.7", "0.2.1"][..],
+            ),
+            (
+                "limit=100",
+                &["1.3.0-beta.1", "1.2.3", "1.2.2", "1.1.0", "0.3.7", "0.2.1"],
+            ),
+            ("major=1&minor=2", &["1.2.3", "1.2.2"]),
+            ("major=0", &["0.3.7", "0.2.1"]),
+            (
+                "include_prerelease=false&major=1",
+                &["1.2.3", "1.2.2", "1.1.0"],
+            ),
+            ("cursor=1.2.2", &["1.1.0", "0.3.7", "0.2.1"]),
+            ("cursor=0.2.1", &[]),
+            ("since=2025-02-01", &["1.3.0-beta.1", "1.2.3", "1.2.2"]),
+            ("until=2025-02-01", &["1.1.0", "0.3.7"]),
+            ("since=2025-03-01T12:34:57Z", &["1.3.0-beta.1"]),
+            (
+                "since=2025-03-01T13:34:56%2B01:00&until=2025-03-02",
+                &["1.2.3"],
+            ),
+        ] {
+            let uri = format!("/api/v1/versions?{}", query);
+            let response = call_service(&app, TestRequest::get().uri(&uri).to_request()).await;
+            assert_eq!(response.status(), StatusCode::OK, "{}", query);
+            assert!(response.headers().get(LINK).is_none(), "{}", query);
+
+            let body: Value = read_body_json(response).await;
+            let versions: Vec<&str> = body["versions"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|v| v["version"].as_str().unwrap())
+                .collect();
+            assert_eq!(versions, expected, "{}", query);
+            assert!(body["next_cursor"].is_null(), "{}", query);
+        }
+
+        for query in [
+            "limit=0",
+            "limit=101",
+            "limit=-1",
+            "minor=2",
+            "cursor=latest",
+            "since=yesterday",
+            "until=1969-12-31",
+            "since=2025-02-30",
+        ] {
+            let uri = format!("/api/v1/versions?{}", query);
+            let response = call_service(&app, TestRequest::get().uri(&uri).to_request()).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", query);
+        }
+    }
+
+    #[actix_web::test]
+    async fn links_to_the_next_page() {
+        let checker = VersionChecker::with_versions(sample_versions(), &[]).await;
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(checker))
+                .configure(configure),
+        )
+        .await;
+
+        let mut uri = "/api/v1/versions?cursor=9.9.9&limit=2&include_prerelease=false".to_string();
+        let mut pages = Vec::new();
+        loop {
+            let response = call_service(&app, TestRequest::get().uri(&uri).to_request()).await;
+            assert_eq!(response.status(), StatusCode::OK, "{}", uri);
+            let link = response
+                .headers()
+                .get(LINK)
+                .map(|link| link.to_str().unwrap().to_string());
+
+            let body: Value = read_body_json(response).await;
+            let versions: Vec<String> = body["versions"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|v| v["version"].as_str().unwrap().to_string())
+                .collect();
+
+            let Some(link) = link else {
+                assert!(body["next_cursor"].is_null());
+                pages.push(versions);
+                break;
+            };
+            let next_cursor = body["next_cursor"].as_str().unwrap();
+            assert_eq!(Some(next_cursor), versions.last().map(String::as_str));
+            assert_eq!(
+                link,
+                format!(
+                    "</api/v1/versions?limit=2&include_prerelease=false&cursor={}>; rel=\"next\"",
+                    next_cursor
+                )
+            );
+
+            pages.push(versions);
+            uri = link
+                .strip_prefix('<')
+                .and_then(|link| link.strip_suffix(">; rel=\"next\""))
+                .unwrap()
+                .to_string();
+        }
+
+        assert_eq!(
+            pages,
+            [
+                vec!["1.2.3", "1.2.2"],
+                vec!["1.1.0", "0.3.7"],
+                vec!["0.2.1"]
+            ]
+        );
+    }
+
+    #[actix_web::test]
+    async fn pins_every_file_of_a_snippet() {
+        let checker = VersionChecker::with_versions(sample_versions(), &[]).await;
+        let esm_url = "https://cdn.jsdelivr.net/npm/highlight-it@1.2.3/+esm";
+        let style_url = "https://cdn.jsdelivr.net/npm/highlight-it@1.2.3/dist/styles/nord.min.css";
+        checker.insert_file_sri_hash(esm_url, "sha512-esm").await;
+        checker.insert_file_sri_hash(style_url, "sha512-nord").await;
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(checker))
+                .configure(configure),
+        )
+        .await;
+
+        for (format, script_url, script_sri_hash) in [
+            ("html", &get_cdn_url("1.2.3")[..], "sha512-1.2.3"),
+            ("jsx", &get_cdn_url("1.2.3"), "sha512-1.2.3"),
+            ("markdown", &get_cdn_url("1.2.3"), "sha512-1.2.3"),
+            ("importmap", esm_url, "sha512-esm"),
+        ] {
+            let uri = format!("/api/v1/snippet?version=1.2.3&style=nord&format={}", format);
+            let response = call_service(&app, TestRequest::get().uri(&uri).to_request()).await;
+            assert_eq!(response.status(), StatusCode::OK, "{}", format);
+
+            let body = String::from_utf8(read_body(response).await.to_vec()).unwrap();
+            for expected in [script_url, script_sri_hash, style_url, "sha512-nord"] {
+                assert!(body.contains(expected), "{}: {}", format, body);
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn asks_to_retry_until_a_version_is_loaded() {
+        let checker = VersionChecker::with_versions(Vec::new(), &[]).await;
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(checker))
+                .configure(configure),
+        )
+        .await;
+
+        for uri in ["/api/latest", "/api/v1/latest", "/api/v1/snippet"] {
+            let response = call_service(&app, TestRequest::get().uri(uri).to_request()).await;
+            assert_eq!(
+                response.status(),
+                StatusCode::SERVICE_UNAVAILABLE,
+                "{}",
+                uri
+            );
+            assert_eq!(
+                response.headers().get(RETRY_AFTER).unwrap(),
+                "10",
+                "{}",
+                uri
+            );
+            assert_eq!(response.headers().get("Cache-Control").unwrap(), "no-store");
+
+            let body: Value = read_body_json(response).await;
+            assert_eq!(body["code"], "unavailable", "{}", uri);
+        }
+    }
+}
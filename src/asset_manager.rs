@@ -1,102 +1,396 @@
+use crate::template::CompiledTemplate;
+use actix_web::web::Bytes;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::SystemTime;
+use tokio::sync::{Mutex, RwLock};
+use tracing::{debug, info};
+
+const BROTLI_QUALITY: u32 = 11;
+const BROTLI_WINDOW: u32 = 22;
+const ZSTD_LEVEL: i32 = 19;
+const RENDER_CACHE_LIMIT: usize = 256;
+const FINGERPRINT_LENGTH: usize = 8;
+
+/// Extensions served from the static directory, with their MIME type and
+/// whether building precompressed variants is worthwhile.
+const ASSET_TYPES: &[(&str, &str, bool)] = &[
+    ("js", "application/javascript", true),
+    ("mjs", "application/javascript", true),
+    ("css", "text/css", true),
+    ("json", "application/json", true),
+    ("webmanifest", "application/manifest+json", true),
+    ("xml", "application/xml", true),
+    ("txt", "text/plain; charset=utf-8", true),
+    ("svg", "image/svg+xml", true),
+    ("ico", "image/x-icon", true),
+    ("png", "image/png", false),
+    ("jpg", "image/jpeg", false),
+    ("jpeg", "image/jpeg", false),
+    ("gif", "image/gif", false),
+    ("webp", "image/webp", false),
+    ("avif", "image/avif", false),
+    ("ttf", "font/ttf", true),
+    ("otf", "font/otf", true),
+    ("woff", "font/woff", false),
+    ("woff2", "font/woff2", false),
+];
 
 #[derive(Debug, Clone, Copy, PartialEq)]
-pub enum AssetType {
-    JavaScript,
-    CSS,
+pub struct AssetType {
+    pub mime_type: &'static str,
+    pub compressible: bool,
+}
+
+impl AssetType {
+    pub fn from_filename(filename: &str) -> Option<Self> {
+        let (_, extension) = filename.rsplit_once('.')?;
+        let extension = extension.to_ascii_lowercase();
+
+        ASSET_TYPES
+            .iter()
+            .find(|(known, _, _)| *known == extension)
+            .map(|(_, mime_type, compressible)| Self {
+                mime_type,
+                compressible: *compressible,
+            })
+    }
+
+    /// Whether the asset is text that may contain the `VERSION` placeholder
+    pub fn is_text(&self) -> bool {
+        self.mime_type.starts_with("text/")
+            || self.mime_type.ends_with("javascript")
+            || self.mime_type.ends_with("json")
+            || self.mime_type.ends_with("xml")
+    }
+}
+
+/// Content together with its precompressed variants. A variant is only kept
+/// when it is smaller than the identity encoding.
+#[derive(Debug, Clone)]
+pub struct EncodedContent {
+    /// Hex digest of the identity content, used as the base of strong ETags
+    pub hash: String,
+    pub identity: Bytes,
+    pub brotli: Option<Bytes>,
+    pub gzip: Option<Bytes>,
+    pub zstd: Option<Bytes>,
+}
+
+impl EncodedContent {
+    pub fn new(content: &[u8]) -> Self {
+        let keep_smaller = |encoded: std::io::Result<Vec<u8>>| {
+            encoded
+                .ok()
+                .filter(|encoded| encoded.len() < content.len())
+                .map(Bytes::from)
+        };
+
+        Self {
+            hash: content_hash(content),
+            identity: Bytes::copy_from_slice(content),
+            brotli: keep_smaller(compress_brotli(content)),
+            gzip: keep_smaller(compress_gzip(content)),
+            zstd: keep_smaller(zstd::bulk::compress(content, ZSTD_LEVEL)),
+        }
+    }
+
+    /// Wraps content that is already compressed, such as images and fonts.
+    pub fn uncompressed(content: &[u8]) -> Self {
+        Self {
+            hash: content_hash(content),
+            identity: Bytes::copy_from_slice(content),
+            brotli: None,
+            gzip: None,
+            zstd: None,
+        }
+    }
+}
+
+fn content_hash(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
+
+/// Inserts a content fingerprint after the file stem, turning `main.min.css`
+/// into `main.<fingerprint>.min.css`.
+fn fingerprint_filename(filename: &str, hash: &str) -> String {
+    let fingerprint = &hash[..FINGERPRINT_LENGTH.min(hash.len())];
+    let (directory, name) = match filename.rsplit_once('/') {
+        Some((directory, name)) => (format!("{}/", directory), name),
+        None => (String::new(), filename),
+    };
+
+    match name.split_once('.') {
+        Some((stem, extension)) => format!("{}{}.{}.{}", directory, stem, fingerprint, extension),
+        None => format!("{}{}.{}", directory, name, fingerprint),
+    }
+}
+
+/// Name under which an unminified source is served when its root has no
+/// minified build of it, turning `main.css` into `main.min.css`. Overlay
+/// roots are not processed by build.js, so their sources are served as is.
+fn minified_name(filename: &str) -> Option<String> {
+    for extension in ["js", "css", "html"] {
+        if let Some(stem) = filename.strip_suffix(&format!(".{}", extension)) {
+            if stem.ends_with(".min") {
+                return None;
+            }
+            return Some(format!("{}.min.{}", stem, extension));
+        }
+    }
+
+    None
+}
+
+/// Resolves the name a file is registered under, or `None` when the file is
+/// a source whose minified build sits next to it.
+fn registered_name(path: &Path, name: &str) -> Option<String> {
+    match minified_name(name) {
+        Some(minified) if path.with_file_name(&minified).exists() => None,
+        Some(minified) => Some(minified),
+        None => Some(name.to_string()),
+    }
+}
+
+fn compress_brotli(content: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut output = Vec::new();
+    {
+        let mut writer =
+            brotli::CompressorWriter::new(&mut output, 4096, BROTLI_QUALITY, BROTLI_WINDOW);
+        writer.write_all(content)?;
+        writer.flush()?;
+    }
+    Ok(output)
+}
+
+fn compress_gzip(content: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(content)?;
+    encoder.finish()
 }
 
 #[derive(Debug, Clone)]
 pub struct Asset {
-    pub content: String,
+    pub content: Bytes,
     pub asset_type: AssetType,
+    pub encoded: Arc<EncodedContent>,
+    pub modified: SystemTime,
+    /// Filename with the content fingerprint, served with immutable caching
+    pub fingerprinted_name: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Template {
+    pub compiled: CompiledTemplate,
+    pub modified: SystemTime,
+}
+
+/// Encoded variants of the versioned text assets of one version, by
+/// fingerprinted name
+type VersionedAssets = HashMap<String, Arc<EncodedContent>>;
+
+/// Encoded renderings that were not precomputed, evicting the least
+/// recently used one when full.
+#[derive(Default)]
+struct RenderCache {
+    entries: HashMap<String, (Arc<EncodedContent>, u64)>,
+    clock: u64,
+}
+
+impl RenderCache {
+    fn get(&mut self, key: &str) -> Option<Arc<EncodedContent>> {
+        self.clock += 1;
+        let (encoded, last_used) = self.entries.get_mut(key)?;
+        *last_used = self.clock;
+        Some(Arc::clone(encoded))
+    }
+
+    fn insert(&mut self, key: String, encoded: Arc<EncodedContent>) {
+        if self.entries.len() >= RENDER_CACHE_LIMIT && !self.entries.contains_key(&key) {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+
+        self.clock += 1;
+        self.entries.insert(key, (encoded, self.clock));
+    }
 }
 
 pub struct AssetManager {
     static_assets: Arc<RwLock<HashMap<String, Asset>>>,
-    templates: Arc<RwLock<HashMap<String, String>>>,
+    fingerprints: Arc<RwLock<HashMap<String, String>>>,
+    templates: Arc<RwLock<HashMap<String, Template>>>,
+    /// Text assets with the `VERSION` placeholder replaced, by version
+    versioned: Arc<RwLock<HashMap<String, VersionedAssets>>>,
+    rendered: Arc<Mutex<RenderCache>>,
 }
 
 impl AssetManager {
-    pub async fn new() -> Result<Self, std::io::Error> {
+    /// Loads assets and templates from the given roots, which are listed in
+    /// priority order. Files in earlier roots override files of the same name
+    /// in later ones.
+    pub async fn new(
+        asset_roots: &[String],
+        template_roots: &[String],
+    ) -> Result<Self, std::io::Error> {
         let manager = Self {
             static_assets: Arc::new(RwLock::new(HashMap::new())),
+            fingerprints: Arc::new(RwLock::new(HashMap::new())),
             templates: Arc::new(RwLock::new(HashMap::new())),
+            versioned: Arc::new(RwLock::new(HashMap::new())),
+            rendered: Arc::new(Mutex::new(RenderCache::default())),
         };
 
-        manager.load_static_files().await?;
-        manager.load_template_files().await?;
+        manager.load_static_files(asset_roots).await?;
+        manager.load_template_files(template_roots).await?;
 
         Ok(manager)
     }
 
-    async fn load_static_files(&self) -> Result<(), std::io::Error> {
-        let static_dir = PathBuf::from("static");
-        let entries = fs::read_dir(&static_dir)?;
-
+    async fn load_static_files(&self, roots: &[String]) -> Result<(), std::io::Error> {
         let mut assets = self.static_assets.write().await;
+        let mut fingerprints = self.fingerprints.write().await;
+
+        for root in roots.iter().rev() {
+            let static_dir = PathBuf::from(root);
+            let loaded = assets.len();
+            Self::load_static_dir(&static_dir, "", &mut assets, &mut fingerprints)?;
+            info!(root = %root, assets = assets.len() - loaded, "Loaded static assets");
+        }
+
+        Ok(())
+    }
+
+    fn load_static_dir(
+        dir: &Path,
+        prefix: &str,
+        assets: &mut HashMap<String, Asset>,
+        fingerprints: &mut HashMap<String, String>,
+    ) -> Result<(), std::io::Error> {
+        let entries = fs::read_dir(dir)?;
 
         for entry in entries {
             let entry = entry?;
             let path = entry.path();
 
-            if path.is_file() {
-                let filename =
-                    path.file_name()
-                        .and_then(|name| name.to_str())
-                        .ok_or_else(|| {
-                            std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid filename")
-                        })?;
-
-                if filename.ends_with(".min.js") || filename.ends_with(".min.css") {
-                    let content = fs::read_to_string(&path)?;
-
-                    let asset_type = if filename.ends_with(".min.js") {
-                        AssetType::JavaScript
-                    } else {
-                        AssetType::CSS
-                    };
-
-                    assets.insert(
-                        filename.to_string(),
-                        Asset {
-                            content,
-                            asset_type,
-                        },
-                    );
+            let name = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .ok_or_else(|| {
+                    std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid filename")
+                })?;
+
+            if path.is_dir() {
+                let prefix = format!("{}{}/", prefix, name);
+                Self::load_static_dir(&path, &prefix, assets, fingerprints)?;
+                continue;
+            }
+
+            if !path.is_file() {
+                continue;
+            }
+
+            let filename = match registered_name(&path, name) {
+                Some(registered) => format!("{}{}", prefix, registered),
+                None => continue,
+            };
+
+            if let Some(asset_type) = AssetType::from_filename(&filename) {
+                let content = Bytes::from(fs::read(&path)?);
+
+                let encoded = if asset_type.compressible {
+                    EncodedContent::new(&content)
+                } else {
+                    EncodedContent::uncompressed(&content)
+                };
+                let encoded = Arc::new(encoded);
+                let modified = entry.metadata()?.modified()?;
+                let fingerprinted_name = fingerprint_filename(&filename, &encoded.hash);
+
+                let previous = assets.insert(
+                    filename.clone(),
+                    Asset {
+                        content,
+                        asset_type,
+                        encoded,
+                        modified,
+                        fingerprinted_name: fingerprinted_name.clone(),
+                    },
+                );
+                if let Some(previous) = previous {
+                    debug!(asset = %filename, path = %path.display(), "Overriding asset");
+                    fingerprints.remove(&previous.fingerprinted_name);
                 }
+                debug!(asset = %filename, fingerprinted = %fingerprinted_name, "Loaded asset");
+                fingerprints.insert(fingerprinted_name, filename);
             }
         }
 
         Ok(())
     }
 
-    async fn load_template_files(&self) -> Result<(), std::io::Error> {
-        let templates_dir = PathBuf::from("templates");
-        let entries = fs::read_dir(&templates_dir)?;
-
+    async fn load_template_files(&self, roots: &[String]) -> Result<(), std::io::Error> {
+        let assets = self.static_assets.read().await;
         let mut templates = self.templates.write().await;
 
-        for entry in entries {
-            let entry = entry?;
-            let path = entry.path();
+        for root in roots.iter().rev() {
+            let templates_dir = PathBuf::from(root);
+            let entries = fs::read_dir(&templates_dir)?;
+
+            for entry in entries {
+                let entry = entry?;
+                let path = entry.path();
+
+                if !path.is_file() {
+                    continue;
+                }
+
+                let name = path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .ok_or_else(|| {
+                        std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid filename")
+                    })?;
 
-            if path.is_file() {
-                let filename =
-                    path.file_name()
-                        .and_then(|name| name.to_str())
-                        .ok_or_else(|| {
-                            std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid filename")
-                        })?;
+                let filename = match registered_name(&path, name) {
+                    Some(registered) => registered,
+                    None => continue,
+                };
 
                 if filename.ends_with(".html") {
-                    let content = fs::read_to_string(&path)?;
+                    let mut content = fs::read_to_string(&path)?;
+                    let modified = entry.metadata()?.modified()?;
+
+                    for (name, asset) in assets.iter() {
+                        for prefix in ["\"/static/", "\"/static/{{ version }}/"] {
+                            content = content.replace(
+                                &format!("{}{}\"", prefix, name),
+                                &format!("{}{}\"", prefix, asset.fingerprinted_name),
+                            );
+                        }
+                    }
 
-                    templates.insert(filename.to_string(), content);
+                    let compiled = CompiledTemplate::compile(&content).map_err(|e| {
+                        std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            format!("Invalid template {}: {}", path.display(), e),
+                        )
+                    })?;
+
+                    debug!(template = %filename, path = %path.display(), "Loaded template");
+                    templates.insert(filename.to_string(), Template { compiled, modified });
                 }
             }
         }
@@ -104,6 +398,7 @@ impl AssetManager {
         Ok(())
     }
 
+    /// Looks up an asset by its plain or fingerprinted filename.
     pub async fn get_asset(&self, filename: &str) -> Option<Asset> {
         let assets = self.static_assets.read().await;
 
@@ -111,18 +406,124 @@ impl AssetManager {
             return Some(asset.clone());
         }
 
-        None
+        let fingerprints = self.fingerprints.read().await;
+        fingerprints
+            .get(filename)
+            .and_then(|name| assets.get(name))
+            .cloned()
     }
 
-    pub async fn get_template(&self, filename: &str) -> Option<String> {
+    pub async fn get_template(&self, filename: &str) -> Option<Template> {
         let templates = self.templates.read().await;
         templates.get(filename).cloned()
     }
 
+    /// Precomputes the encoded variants of the text assets with the
+    /// `VERSION` placeholder for each of `versions`, and drops those of
+    /// versions no longer listed. Versions prepared before are kept as is.
+    pub async fn prepare_versions(&self, versions: &[String]) {
+        let templated: Vec<(String, String)> = self
+            .static_assets
+            .read()
+            .await
+            .values()
+            .filter(|asset| asset.asset_type.is_text())
+            .filter_map(|asset| {
+                let content = std::str::from_utf8(&asset.content).ok()?;
+                content
+                    .contains("VERSION")
+                    .then(|| (asset.fingerprinted_name.clone(), content.to_string()))
+            })
+            .collect();
+
+        let missing: Vec<String> = {
+            let versioned = self.versioned.read().await;
+            versions
+                .iter()
+                .filter(|version| !versioned.contains_key(*version))
+                .cloned()
+                .collect()
+        };
+
+        let start = std::time::Instant::now();
+        let prepared = tokio::task::spawn_blocking(move || {
+            missing
+                .into_iter()
+                .map(|version| {
+                    let assets = templated
+                        .iter()
+                        .map(|(name, content)| {
+                            let content = content.replace("VERSION", &version);
+                            (
+                                name.clone(),
+                                Arc::new(EncodedContent::new(content.as_bytes())),
+                            )
+                        })
+                        .collect::<VersionedAssets>();
+                    (version, assets)
+                })
+                .collect::<Vec<_>>()
+        })
+        .await
+        .unwrap_or_default();
+
+        let mut versioned = self.versioned.write().await;
+        versioned.retain(|version, _| versions.contains(version));
+        if !prepared.is_empty() {
+            debug!(
+                versions = prepared.len(),
+                elapsed_ms = start.elapsed().as_millis() as u64,
+                "Prepared versioned assets"
+            );
+        }
+        versioned.extend(prepared);
+    }
+
+    /// Returns the encoded variants of an asset served under `version`,
+    /// with the `VERSION` placeholder of text assets replaced. Versions not
+    /// prepared yet are encoded on demand and kept in a bounded cache.
+    pub async fn encode_versioned(&self, version: &str, asset: &Asset) -> Arc<EncodedContent> {
+        let content = match std::str::from_utf8(&asset.content) {
+            Ok(content) if asset.asset_type.is_text() && content.contains("VERSION") => content,
+            _ => return Arc::clone(&asset.encoded),
+        };
+
+        if let Some(encoded) = self
+            .versioned
+            .read()
+            .await
+            .get(version)
+            .and_then(|assets| assets.get(&asset.fingerprinted_name))
+        {
+            return Arc::clone(encoded);
+        }
+
+        self.encode_rendered(content.replace("VERSION", version))
+            .await
+    }
+
+    /// Encoded variants of a rendered page or asset, reused while the same
+    /// content is rendered again.
+    pub async fn encode_rendered(&self, content: String) -> Arc<EncodedContent> {
+        let key = content_hash(content.as_bytes());
+
+        if let Some(encoded) = self.rendered.lock().await.get(&key) {
+            return encoded;
+        }
+
+        let encoded = Arc::new(EncodedContent::new(content.as_bytes()));
+        self.rendered.lock().await.insert(key, Arc::clone(&encoded));
+
+        encoded
+    }
+
     pub fn clone(&self) -> Self {
         Self {
             static_assets: Arc::clone(&self.static_assets),
+            fingerprints: Arc::clone(&self.fingerprints),
             templates: Arc::clone(&self.templates),
+            versioned: Arc::clone(&self.versioned),
+            rendered: Arc::clone(&self.rendered),
         }
     }
 }
@@ -131,7 +532,38 @@ impl Clone for AssetManager {
     fn clone(&self) -> Self {
         Self {
             static_assets: Arc::clone(&self.static_assets),
+            fingerprints: Arc::clone(&self.fingerprints),
             templates: Arc::clone(&self.templates),
+            versioned: Arc::clone(&self.versioned),
+            rendered: Arc::clone(&self.rendered),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_cache_evicts_least_recently_used() {
+        let mut cache = RenderCache::default();
+        for i in 0..RENDER_CACHE_LIMIT {
+            let content = i.to_string();
+            cache.insert(
+                content.clone(),
+                Arc::new(EncodedContent::uncompressed(content.as_bytes())),
+            );
+        }
+
+        assert!(cache.get("0").is_some());
+        cache.insert(
+            "new".to_string(),
+            Arc::new(EncodedContent::uncompressed(b"new")),
+        );
+
+        assert_eq!(cache.entries.len(), RENDER_CACHE_LIMIT);
+        assert!(cache.get("0").is_some());
+        assert!(cache.get("1").is_none());
+        assert!(cache.get("new").is_some());
+    }
+}
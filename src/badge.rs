@@ -0,0 +1,289 @@
+use crate::asset_manager::EncodedContent;
+use crate::config::Badge;
+use crate::template::escape_html;
+use crate::utils::create_encoded_response;
+use crate::version_checker::{VersionChecker, VersionInfo, is_valid_version};
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse, Responder, get, web};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+const LATEST: &str = "latest";
+
+/// Background of the badge for an unknown version
+const NOT_FOUND_COLOR: &str = "#9f9f9f";
+
+/// Horizontal padding around each half of the badge
+const PADDING: f64 = 10.0;
+
+#[derive(Clone, Copy)]
+enum BadgeStyle {
+    Flat,
+    Plastic,
+}
+
+/// Encoded badges for the version state of `updated_at`, by the version
+/// they show or `None` for the not found badge.
+#[derive(Default)]
+struct BadgeCache {
+    updated_at: Option<SystemTime>,
+    badges: HashMap<Option<String>, Arc<EncodedContent>>,
+}
+
+/// Badge look, validated at startup. The style is fixed for the renderer,
+/// so its cache only needs to tell versions apart.
+pub struct BadgeRenderer {
+    label: String,
+    label_color: String,
+    color: String,
+    deprecated_color: String,
+    style: BadgeStyle,
+    cache_control: String,
+    cache: Mutex<BadgeCache>,
+}
+
+/// Accepts "#rgb", "#rrggbb" and plain color names, nothing that could
+/// escape the attribute it is written to.
+fn is_valid_color(color: &str) -> bool {
+    match color.strip_prefix('#') {
+        Some(hex) => matches!(hex.len(), 3 | 6) && hex.chars().all(|c| c.is_ascii_hexdigit()),
+        None => {
+            !color.is_empty() && color.len() <= 20 && color.chars().all(|c| c.is_ascii_alphabetic())
+        }
+    }
+}
+
+/// Approximate advance of a character in 11px Verdana, the font badges are
+/// drawn with.
+fn char_width(character: char) -> f64 {
+    match character {
+        'i' | 'l' | '.' | ',' | ':' | ';' | '\'' | '|' | '!' => 3.0,
+        'f' | 'j' | 't' | ' ' | '(' | ')' | '[' | ']' => 4.0,
+        'r' | '-' => 4.7,
+        'c' | 's' | 'z' => 5.8,
+        'm' => 10.7,
+        'w' => 9.0,
+        'M' | 'W' => 10.0,
+        'A'..='Z' => 7.5,
+        _ => 6.8,
+    }
+}
+
+fn text_width(text: &str) -> f64 {
+    text.chars().map(char_width).sum::<f64>().ceil()
+}
+
+impl BadgeRenderer {
+    pub fn new(config: &Badge) -> Result<Self, Box<dyn std::error::Error>> {
+        for color in [&config.label_color, &config.color, &config.deprecated_color] {
+            if !is_valid_color(color) {
+                return Err(format!("Invalid badge color: {}", color).into());
+            }
+        }
+
+        let style = match config.style.as_str() {
+            "flat" => BadgeStyle::Flat,
+            "plastic" => BadgeStyle::Plastic,
+            other => return Err(format!("Invalid badge style: {}", other).into()),
+        };
+
+        Ok(Self {
+            label: config.label.clone(),
+            label_color: config.label_color.clone(),
+            color: config.color.clone(),
+            deprecated_color: config.deprecated_color.clone(),
+            style,
+            cache_control: format!("public, max-age={}", config.max_age),
+            cache: Mutex::new(BadgeCache::default()),
+        })
+    }
+
+    /// The encoded badge of a version, or of an unknown one for `None`,
+    /// rendered and compressed once per version state.
+    fn badge(
+        &self,
+        version_info: Option<&VersionInfo>,
+        updated_at: SystemTime,
+    ) -> Arc<EncodedContent> {
+        let key = version_info.map(|v| v.version.clone());
+
+        {
+            let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+            if cache.updated_at != Some(updated_at) {
+                cache.updated_at = Some(updated_at);
+                cache.badges.clear();
+            }
+            if let Some(content) = cache.badges.get(&key) {
+                return Arc::clone(content);
+            }
+        }
+
+        let svg = match version_info {
+            Some(version_info) => {
+                let color = match version_info.deprecated {
+                    Some(_) => &self.deprecated_color,
+                    None => &self.color,
+                };
+                self.render(&format!("v{}", version_info.version), color)
+            }
+            None => self.render("not found", NOT_FOUND_COLOR),
+        };
+        let content = Arc::new(EncodedContent::new(svg.as_bytes()));
+
+        let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        if cache.updated_at == Some(updated_at) {
+            cache.badges.insert(key, Arc::clone(&content));
+        }
+
+        content
+    }
+
+    /// Two-part badge with the label on the left and `message` on `color`.
+    fn render(&self, message: &str, color: &str) -> String {
+        let label_width = text_width(&self.label) + PADDING;
+        let message_width = text_width(message) + PADDING;
+        let width = label_width + message_width;
+        let label_x = label_width / 2.0;
+        let message_x = label_width + message_width / 2.0;
+
+        let (height, radius, text_y, gradient) = match self.style {
+            BadgeStyle::Flat => (
+                20,
+                3,
+                14,
+                r##"<stop offset="0" stop-color="#bbb" stop-opacity=".1"/><stop offset="1" stop-opacity=".1"/>"##,
+            ),
+            BadgeStyle::Plastic => (
+                18,
+                4,
+                13,
+                r##"<stop offset="0" stop-color="#fff" stop-opacity=".7"/><stop offset=".1" stop-color="#aaa" stop-opacity=".1"/><stop offset=".9" stop-opacity=".3"/><stop offset="1" stop-opacity=".5"/>"##,
+            ),
+        };
+
+        let label = escape_html(&self.label);
+        let message = escape_html(message);
+
+        format!(
+            r##"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" role="img" aria-label="{label}: {message}"><title>{label}: {message}</title><linearGradient id="s" x2="0" y2="100%">{gradient}</linearGradient><clipPath id="r"><rect width="{width}" height="{height}" rx="{radius}" fill="#fff"/></clipPath><g clip-path="url(#r)"><rect width="{label_width}" height="{height}" fill="{label_color}"/><rect x="{label_width}" width="{message_width}" height="{height}" fill="{color}"/><rect width="{width}" height="{height}" fill="url(#s)"/></g><g fill="#fff" text-anchor="middle" font-family="Verdana,Geneva,DejaVu Sans,sans-serif" font-size="11"><text x="{label_x}" y="{shadow_y}" fill="#010101" fill-opacity=".3">{label}</text><text x="{label_x}" y="{text_y}">{label}</text><text x="{message_x}" y="{shadow_y}" fill="#010101" fill-opacity=".3">{message}</text><text x="{message_x}" y="{text_y}">{message}</text></g></svg>"##,
+            label_color = self.label_color,
+            shadow_y = text_y + 1,
+        )
+    }
+}
+
+/// Badge showing a version, a dist-tag's version or `latest`. Unknown
+/// versions still get a badge so embedding pages do not show a broken image.
+#[get("/badge/{name}.svg")]
+pub async fn serve_badge(
+    req: HttpRequest,
+    path: web::Path<String>,
+    data: web::Data<VersionChecker>,
+    renderer: web::Data<BadgeRenderer>,
+) -> impl Responder {
+    let name = path.into_inner();
+    // Read first, so a change while resolving the name invalidates the
+    // badge cached below
+    let updated_at = data.get_updated_at().await;
+    let dist_tags = data.get_dist_tags().await;
+
+    let version = match dist_tags.get(&name) {
+        Some(version) => Some(version.clone()),
+        None if name == LATEST => Some(data.get_latest_version_info().await.version),
+        None if is_valid_version(&name) => Some(name),
+        None => None,
+    };
+
+    let version_info = match version {
+        Some(version) => data
+            .get_all_versions()
+            .await
+            .into_iter()
+            .find(|v| v.version == version),
+        None => None,
+    };
+    let status = match version_info {
+        Some(_) => StatusCode::OK,
+        None => StatusCode::NOT_FOUND,
+    };
+
+    let content = renderer.badge(version_info.as_ref(), updated_at);
+
+    // Badges are embedded by READMEs and docs on other origins
+    let builder = HttpResponse::build(status)
+        .content_type("image/svg+xml")
+        .append_header(("Cache-Control", renderer.cache_control.as_str()))
+        .insert_header(("Cross-Origin-Resource-Policy", "cross-origin"))
+        .take();
+
+    create_encoded_response(&req, builder, &content, updated_at)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::version_checker::sample_versions;
+    use std::time::Duration;
+
+    fn renderer() -> BadgeRenderer {
+        BadgeRenderer::new(&Badge::default()).unwrap()
+    }
+
+    #[test]
+    fn validates_config() {
+        for color in ["#abc", "#A0B1C2", "green"] {
+            assert!(is_valid_color(color), "{}", color);
+        }
+        for color in ["", "#abcd", "#ggg", "red\" onload=\"x", "url(#a)"] {
+            assert!(!is_valid_color(color), "{}", color);
+        }
+
+        let style = |style: &str| Badge {
+            style: style.to_string(),
+            ..Badge::default()
+        };
+        assert!(BadgeRenderer::new(&style("plastic")).is_ok());
+        assert!(BadgeRenderer::new(&style("for-the-badge")).is_err());
+    }
+
+    #[test]
+    fn colors_by_deprecation() {
+        let renderer = renderer();
+        let versions = sample_versions();
+        let updated_at = SystemTime::now();
+
+        let badge = |version_info| {
+            let content = renderer.badge(version_info, updated_at);
+            String::from_utf8(content.identity.to_vec()).unwrap()
+        };
+
+        let current = badge(versions.iter().find(|v| v.version == "1.2.3"));
+        assert!(current.contains("v1.2.3") && current.contains(&renderer.color));
+
+        let deprecated = badge(versions.iter().find(|v| v.version == "1.2.2"));
+        assert!(deprecated.contains("v1.2.2") && deprecated.contains(&renderer.deprecated_color));
+
+        let not_found = badge(None);
+        assert!(not_found.contains("not found") && not_found.contains(NOT_FOUND_COLOR));
+    }
+
+    #[test]
+    fn caches_until_the_state_changes() {
+        let renderer = renderer();
+        let mut version_info = sample_versions().remove(1);
+        let updated_at = SystemTime::now();
+
+        let first = renderer.badge(Some(&version_info), updated_at);
+        assert!(Arc::ptr_eq(
+            &first,
+            &renderer.badge(Some(&version_info), updated_at)
+        ));
+
+        version_info.deprecated = Some("Use 2.0.0".to_string());
+        let later = updated_at + Duration::from_secs(1);
+        let second = renderer.badge(Some(&version_info), later);
+        assert!(!Arc::ptr_eq(&first, &second));
+        assert_ne!(first.hash, second.hash);
+    }
+}
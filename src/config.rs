@@ -1,6 +1,8 @@
 use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::env;
 use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::path::Path;
 
 #[derive(Deserialize, Debug, Clone)]
@@ -17,6 +19,188 @@ pub struct Config {
     pub version_check_interval: u64,
     /// Cache directory for version information
     pub cache_dir: Option<String>,
+    /// Static asset directories, highest priority first
+    #[serde(default = "default_asset_roots")]
+    pub asset_roots: Vec<String>,
+    /// Template directories, highest priority first
+    #[serde(default = "default_template_roots")]
+    pub template_roots: Vec<String>,
+    /// PEM certificate chain, serves HTTPS together with `tls_key_path`
+    pub tls_cert_path: Option<String>,
+    /// PEM private key for `tls_cert_path`
+    pub tls_key_path: Option<String>,
+    /// Port of a plain HTTP listener that redirects to HTTPS
+    pub http_redirect_port: Option<u16>,
+    /// Unix socket path to listen on instead of `host` and `port`
+    pub unix_socket: Option<String>,
+    /// Octal permissions of the Unix socket, e.g. "660"
+    pub unix_socket_mode: Option<String>,
+    /// Owner of the Unix socket as "user", "user:group" or ":group"
+    pub unix_socket_owner: Option<String>,
+    /// Public URL of the demo such as "https://hit-demo.example.com", used
+    /// for absolute links in feeds. Defaults to the listening address.
+    pub base_url: Option<String>,
+    /// Address of a separate listener serving `/metrics` instead of the main one
+    pub metrics_addr: Option<String>,
+    /// Bearer token required to read `/metrics`
+    pub metrics_token: Option<String>,
+    /// Per-client request limits for each route group
+    #[serde(default)]
+    pub rate_limits: RateLimits,
+    /// Proxies whose `X-Forwarded-For` header is trusted for the client IP,
+    /// loopback by default so a local reverse proxy's clients are told apart
+    #[serde(default = "default_trusted_proxies")]
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Response headers such as the Content-Security-Policy and framing rules
+    #[serde(default)]
+    pub security_headers: SecurityHeaders,
+    /// Cross-origin access to `/api/`, disabled unless origins are listed
+    #[serde(default)]
+    pub cors: Cors,
+    /// Look of the `/badge/` version badges
+    #[serde(default)]
+    pub badge: Badge,
+    /// Log filter such as "info" or "hit_demo=debug,access=warn", `RUST_LOG` overrides it
+    #[serde(default = "default_log_level")]
+    pub log_level: String,
+    /// Log output format: "text", "json" or "logfmt"
+    #[serde(default = "default_log_format")]
+    pub log_format: String,
+    /// Seconds to let in-flight requests finish after SIGTERM
+    #[serde(default = "default_shutdown_timeout")]
+    pub shutdown_timeout: u64,
+}
+
+/// A token bucket refilled with `rate` requests per second up to `burst`.
+/// A rate of 0 disables the limit.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct RateLimit {
+    pub rate: f64,
+    pub burst: f64,
+}
+
+/// Limits per route group, all disabled by default. Behind a CDN or a proxy
+/// not listed in `trusted_proxies`, every visitor would share one bucket.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct RateLimits {
+    /// Rendered pages such as `/` and `/{version}`, and every other path
+    /// outside `/api` and `/static/` such as badges, feeds and the sitemap
+    pub pages: RateLimit,
+    /// `/api` and everything under it
+    pub api: RateLimit,
+    /// Everything under `/static/`
+    pub static_files: RateLimit,
+    /// Rendered 404 pages, counted in addition to the group of the path
+    pub not_found: RateLimit,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct SecurityHeaders {
+    /// Headers added to or replacing the built-in set, an empty value removes one
+    pub headers: BTreeMap<String, String>,
+    /// Policy replacing the built-in one, `{nonce}` becomes the current nonce
+    pub content_security_policy: Option<String>,
+    /// Send the policy as Content-Security-Policy-Report-Only
+    pub csp_report_only: bool,
+    /// Changes for paths under a prefix, applied from the shortest matching
+    /// prefix to the longest
+    pub overrides: Vec<SecurityHeadersOverride>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct SecurityHeadersOverride {
+    pub path_prefix: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    pub content_security_policy: Option<String>,
+    pub csp_report_only: Option<bool>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Cors {
+    /// Origins such as "https://docs.example.com" allowed to read the API,
+    /// or "*" for any
+    pub allowed_origins: Vec<String>,
+    /// Methods allowed in preflight requests
+    pub allowed_methods: Vec<String>,
+    /// Request headers allowed in preflight requests
+    pub allowed_headers: Vec<String>,
+    /// Response headers readable by the calling page
+    pub exposed_headers: Vec<String>,
+    /// Seconds a browser may cache a preflight response
+    pub max_age: u64,
+}
+
+impl Default for Cors {
+    fn default() -> Self {
+        Self {
+            allowed_origins: Vec::new(),
+            allowed_methods: vec!["GET".to_string(), "HEAD".to_string()],
+            allowed_headers: Vec::new(),
+            exposed_headers: vec!["X-Request-Id".to_string()],
+            max_age: 3600,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Badge {
+    /// Text on the left side
+    pub label: String,
+    /// Background of the label, as "#rgb", "#rrggbb" or a color name
+    pub label_color: String,
+    /// Background of the version
+    pub color: String,
+    /// Background of a version deprecated on npm
+    pub deprecated_color: String,
+    /// "flat" or "plastic"
+    pub style: String,
+    /// Seconds clients and proxies may cache a badge
+    pub max_age: u64,
+}
+
+impl Default for Badge {
+    fn default() -> Self {
+        Self {
+            label: "highlight-it".to_string(),
+            label_color: "#555".to_string(),
+            color: "#007ec6".to_string(),
+            deprecated_color: "#dfb317".to_string(),
+            style: "flat".to_string(),
+            max_age: 300,
+        }
+    }
+}
+
+fn default_asset_roots() -> Vec<String> {
+    vec!["static".to_string()]
+}
+
+fn default_template_roots() -> Vec<String> {
+    vec!["templates".to_string()]
+}
+
+fn default_log_level() -> String {
+    "info".to_string()
+}
+
+fn default_log_format() -> String {
+    "text".to_string()
+}
+
+fn default_trusted_proxies() -> Vec<IpAddr> {
+    vec![
+        IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(Ipv6Addr::LOCALHOST),
+    ]
+}
+
+fn default_shutdown_timeout() -> u64 {
+    30
 }
 
 impl Default for Config {
@@ -28,6 +212,25 @@ impl Default for Config {
             http_timeout: 3,
             version_check_interval: 1800,
             cache_dir: Some("./".to_string()),
+            asset_roots: default_asset_roots(),
+            template_roots: default_template_roots(),
+            tls_cert_path: None,
+            tls_key_path: None,
+            http_redirect_port: None,
+            unix_socket: None,
+            unix_socket_mode: None,
+            unix_socket_owner: None,
+            base_url: None,
+            metrics_addr: None,
+            metrics_token: None,
+            rate_limits: RateLimits::default(),
+            trusted_proxies: default_trusted_proxies(),
+            security_headers: SecurityHeaders::default(),
+            cors: Cors::default(),
+            badge: Badge::default(),
+            log_level: default_log_level(),
+            log_format: default_log_format(),
+            shutdown_timeout: default_shutdown_timeout(),
         }
     }
 }
@@ -42,6 +245,20 @@ impl Config {
     pub fn server_addr(&self) -> String {
         format!("{}:{}", self.host, self.port)
     }
+
+    pub fn redirect_addr(&self) -> Option<String> {
+        self.http_redirect_port
+            .map(|port| format!("{}:{}", self.host, port))
+    }
+
+    /// Certificate and key paths, if TLS is configured.
+    pub fn tls_paths(&self) -> Result<Option<(&str, &str)>, Box<dyn std::error::Error>> {
+        match (&self.tls_cert_path, &self.tls_key_path) {
+            (Some(cert_path), Some(key_path)) => Ok(Some((cert_path, key_path))),
+            (None, None) => Ok(None),
+            _ => Err("tls_cert_path and tls_key_path must be set together".into()),
+        }
+    }
 }
 
 pub fn load_config() -> Result<Config, Box<dyn std::error::Error>> {
@@ -0,0 +1,420 @@
+use crate::api;
+use crate::config::Cors;
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{
+    ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN,
+    ACCESS_CONTROL_EXPOSE_HEADERS, ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_HEADERS,
+    ACCESS_CONTROL_REQUEST_METHOD, HeaderName, HeaderValue, ORIGIN, VARY,
+};
+use actix_web::http::{Method, StatusCode};
+use actix_web::middleware::Next;
+use actix_web::{Error, HttpResponse, web};
+
+const ANY_ORIGIN: &str = "*";
+
+const CROSS_ORIGIN_RESOURCE_POLICY: HeaderName =
+    HeaderName::from_static("cross-origin-resource-policy");
+
+/// Cross-origin rules for the API, validated at startup.
+pub struct CorsPolicy {
+    any_origin: bool,
+    origins: Vec<String>,
+    methods: Vec<Method>,
+    allow_methods: HeaderValue,
+    /// Lowercase names, or empty to allow no custom headers
+    headers: Vec<String>,
+    expose_headers: Option<HeaderValue>,
+    max_age: HeaderValue,
+}
+
+impl CorsPolicy {
+    /// Builds the policy, or `None` if no origin is allowed.
+    pub fn new(config: &Cors) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        if config.allowed_origins.is_empty() {
+            return Ok(None);
+        }
+
+        let any_origin = config
+            .allowed_origins
+            .iter()
+            .any(|origin| origin == ANY_ORIGIN);
+        let mut origins = Vec::new();
+        for origin in &config.allowed_origins {
+            if origin == ANY_ORIGIN {
+                continue;
+            }
+            let origin = origin.trim_end_matches('/');
+            if !(origin.starts_with("https://") || origin.starts_with("http://"))
+                || HeaderValue::from_str(origin).is_err()
+            {
+                return Err(format!("Invalid CORS origin: {}", origin).into());
+            }
+            origins.push(origin.to_ascii_lowercase());
+        }
+
+        let mut methods = Vec::new();
+        for method in &config.allowed_methods {
+            let method = Method::from_bytes(method.to_ascii_uppercase().as_bytes())
+                .map_err(|_| format!("Invalid CORS method: {}", method))?;
+            methods.push(method);
+        }
+        let allow_methods = HeaderValue::from_str(
+            &methods
+                .iter()
+                .map(Method::as_str)
+                .collect::<Vec<_>>()
+                .join(", "),
+        )?;
+
+        let mut headers = Vec::new();
+        for header in &config.allowed_headers {
+            HeaderName::from_bytes(header.as_bytes())
+                .map_err(|_| format!("Invalid CORS allowed header: {}", header))?;
+            headers.push(header.to_ascii_lowercase());
+        }
+
+        for header in &config.exposed_headers {
+            HeaderName::from_bytes(header.as_bytes())
+                .map_err(|_| format!("Invalid CORS exposed header: {}", header))?;
+        }
+        let expose_headers = if config.exposed_headers.is_empty() {
+            None
+        } else {
+            Some(HeaderValue::from_str(&config.exposed_headers.join(", "))?)
+        };
+
+        Ok(Some(Self {
+            any_origin,
+            origins,
+            methods,
+            allow_methods,
+            headers,
+            expose_headers,
+            max_age: HeaderValue::from(config.max_age),
+        }))
+    }
+
+    fn allows_origin(&self, origin: &str) -> bool {
+        self.any_origin
+            || self
+                .origins
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(origin))
+    }
+
+    /// The `Access-Control-Allow-Origin` value for an allowed origin. A
+    /// wildcard policy answers with `*` so shared caches can reuse it.
+    fn allow_origin(&self, origin: &HeaderValue) -> HeaderValue {
+        if self.any_origin {
+            HeaderValue::from_static(ANY_ORIGIN)
+        } else {
+            origin.clone()
+        }
+    }
+
+    fn allows_request_headers(&self, requested: Option<&HeaderValue>) -> bool {
+        let Some(requested) = requested else {
+            return true;
+        };
+        let Ok(requested) = requested.to_str() else {
+            return false;
+        };
+
+        requested
+            .split(',')
+            .map(str::trim)
+            .filter(|header| !header.is_empty())
+            .all(|header| {
+                self.headers
+                    .iter()
+                    .any(|allowed| allowed.eq_ignore_ascii_case(header))
+            })
+    }
+
+    /// Answers a preflight request, with a 403 if the origin, method or
+    /// headers are not allowed.
+    fn preflight(&self, req: &ServiceRequest, origin: &HeaderValue) -> HttpResponse {
+        let headers = req.headers();
+        let method_allowed = headers
+            .get(ACCESS_CONTROL_REQUEST_METHOD)
+            .and_then(|method| Method::from_bytes(method.as_bytes()).ok())
+            .is_some_and(|method| self.methods.contains(&method));
+        let origin_allowed = origin
+            .to_str()
+            .is_ok_and(|origin| self.allows_origin(origin));
+        let requested_headers = headers.get(ACCESS_CONTROL_REQUEST_HEADERS);
+
+        if !origin_allowed || !method_allowed || !self.allows_request_headers(requested_headers) {
+            let mut response = api::error_response(
+                req.request(),
+                StatusCode::FORBIDDEN,
+                "CORS request not allowed",
+            );
+            response
+                .headers_mut()
+                .append(VARY, HeaderValue::from_static("Origin"));
+            return response;
+        }
+
+        let mut response = HttpResponse::NoContent();
+        response
+            .insert_header((ACCESS_CONTROL_ALLOW_ORIGIN, self.allow_origin(origin)))
+            .insert_header((ACCESS_CONTROL_ALLOW_METHODS, self.allow_methods.clone()))
+            .insert_header((ACCESS_CONTROL_MAX_AGE, self.max_age.clone()))
+            .insert_header((CROSS_ORIGIN_RESOURCE_POLICY, "cross-origin"))
+            .append_header((VARY, "Origin"));
+        if let Some(requested_headers) = requested_headers {
+            response.insert_header((ACCESS_CONTROL_ALLOW_HEADERS, requested_headers.clone()));
+        }
+        response.finish()
+    }
+}
+
+/// Middleware answering CORS preflight requests under `/api` and adding the
+/// CORS headers to API responses for allowed origins. API responses may also
+/// be embedded cross-origin, so they get a `cross-origin` resource policy.
+pub async fn handle_cors(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let policy = req.app_data::<web::Data<CorsPolicy>>().cloned();
+
+    // Only the JSON API can be read cross-origin
+    let Some(policy) = policy.filter(|_| api::is_api_path(req.path())) else {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_left_body);
+    };
+
+    let origin = req.headers().get(ORIGIN).cloned();
+
+    if let Some(origin) = &origin {
+        if req.method() == Method::OPTIONS
+            && req.headers().contains_key(ACCESS_CONTROL_REQUEST_METHOD)
+        {
+            let response = policy.preflight(&req, origin);
+            return Ok(req.into_response(response).map_into_right_body());
+        }
+    }
+
+    let mut response = next.call(req).await?;
+    let headers = response.headers_mut();
+
+    headers.insert(
+        CROSS_ORIGIN_RESOURCE_POLICY,
+        HeaderValue::from_static("cross-origin"),
+    );
+
+    if !policy.any_origin {
+        headers.append(VARY, HeaderValue::from_static("Origin"));
+    }
+
+    if let Some(origin) = origin.filter(|origin| {
+        origin
+            .to_str()
+            .is_ok_and(|origin| policy.allows_origin(origin))
+    }) {
+        headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, policy.allow_origin(&origin));
+        if let Some(expose_headers) = &policy.expose_headers {
+            headers.insert(ACCESS_CONTROL_EXPOSE_HEADERS, expose_headers.clone());
+        }
+    }
+
+    Ok(response.map_into_left_body())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{App, HttpResponse, middleware, test};
+
+    const DOCS: &str = "https://docs.example.com";
+
+    fn config(origins: &[&str]) -> Cors {
+        Cors {
+            allowed_origins: origins.iter().map(|origin| origin.to_string()).collect(),
+            allowed_headers: vec!["Content-Type".to_string()],
+            ..Cors::default()
+        }
+    }
+
+    async fn call(origins: &[&str], req: test::TestRequest) -> ServiceResponse {
+        let policy = CorsPolicy::new(&config(origins)).unwrap().unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(policy))
+                .wrap(middleware::from_fn(handle_cors))
+                .default_service(web::to(HttpResponse::Ok)),
+        )
+        .await;
+        test::call_service(&app, req.to_request())
+            .await
+            .map_into_boxed_body()
+    }
+
+    fn preflight(path: &str, origin: &str, method: &str) -> test::TestRequest {
+        test::TestRequest::default()
+            .method(Method::OPTIONS)
+            .uri(path)
+            .insert_header((ORIGIN, origin))
+            .insert_header((ACCESS_CONTROL_REQUEST_METHOD, method))
+    }
+
+    fn header(response: &ServiceResponse, name: HeaderName) -> Option<&str> {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    }
+
+    #[actix_web::test]
+    async fn validates_config() {
+        assert!(CorsPolicy::new(&config(&[])).unwrap().is_none());
+        assert!(CorsPolicy::new(&config(&["docs.example.com"])).is_err());
+        assert!(CorsPolicy::new(&config(&["ftp://docs.example.com"])).is_err());
+        assert!(
+            CorsPolicy::new(&Cors {
+                allowed_methods: vec!["G ET".to_string()],
+                ..config(&[DOCS])
+            })
+            .is_err()
+        );
+        assert!(
+            CorsPolicy::new(&Cors {
+                allowed_headers: vec!["Bad Header".to_string()],
+                ..config(&[DOCS])
+            })
+            .is_err()
+        );
+        assert!(
+            CorsPolicy::new(&Cors {
+                exposed_headers: vec!["Bad:Header".to_string()],
+                ..config(&[DOCS])
+            })
+            .is_err()
+        );
+
+        let policy = CorsPolicy::new(&config(&["https://Docs.Example.com/"]))
+            .unwrap()
+            .unwrap();
+        assert!(policy.allows_origin(DOCS));
+        assert!(!policy.allows_origin("https://docs.example.com.evil"));
+    }
+
+    #[actix_web::test]
+    async fn answers_allowed_preflight() {
+        let response = call(
+            &[DOCS],
+            preflight("/api/v1/latest", DOCS, "GET")
+                .insert_header((ACCESS_CONTROL_REQUEST_HEADERS, "content-type")),
+        )
+        .await;
+
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(header(&response, ACCESS_CONTROL_ALLOW_ORIGIN), Some(DOCS));
+        assert_eq!(
+            header(&response, ACCESS_CONTROL_ALLOW_METHODS),
+            Some("GET, HEAD")
+        );
+        assert_eq!(
+            header(&response, ACCESS_CONTROL_ALLOW_HEADERS),
+            Some("content-type")
+        );
+        assert_eq!(header(&response, ACCESS_CONTROL_MAX_AGE), Some("3600"));
+        assert_eq!(header(&response, VARY), Some("Origin"));
+    }
+
+    #[actix_web::test]
+    async fn rejects_disallowed_preflight() {
+        for req in [
+            preflight("/api/v1/latest", "https://evil.example.com", "GET"),
+            preflight("/api/v1/latest", DOCS, "POST"),
+            preflight("/api/v1/latest", DOCS, "GET")
+                .insert_header((ACCESS_CONTROL_REQUEST_HEADERS, "content-type, x-secret")),
+        ] {
+            let response = call(&[DOCS], req).await;
+
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+            assert_eq!(header(&response, ACCESS_CONTROL_ALLOW_ORIGIN), None);
+            assert_eq!(header(&response, VARY), Some("Origin"));
+
+            let body: serde_json::Value = test::read_body_json(response).await;
+            assert_eq!(body["code"], "forbidden");
+            assert_eq!(body["message"], "CORS request not allowed");
+        }
+    }
+
+    #[actix_web::test]
+    async fn answers_wildcard_with_any_origin() {
+        let response = call(&["*"], preflight("/api/v1/latest", DOCS, "HEAD")).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(header(&response, ACCESS_CONTROL_ALLOW_ORIGIN), Some("*"));
+
+        let response = call(
+            &["*"],
+            test::TestRequest::get()
+                .uri("/api/v1/latest")
+                .insert_header((ORIGIN, DOCS)),
+        )
+        .await;
+        assert_eq!(header(&response, ACCESS_CONTROL_ALLOW_ORIGIN), Some("*"));
+        assert_eq!(header(&response, VARY), None);
+    }
+
+    #[actix_web::test]
+    async fn adds_headers_to_api_responses() {
+        let response = call(
+            &[DOCS],
+            test::TestRequest::get()
+                .uri("/api/v1/latest")
+                .insert_header((ORIGIN, DOCS)),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(header(&response, ACCESS_CONTROL_ALLOW_ORIGIN), Some(DOCS));
+        assert_eq!(
+            header(&response, ACCESS_CONTROL_EXPOSE_HEADERS),
+            Some("X-Request-Id")
+        );
+        assert_eq!(header(&response, VARY), Some("Origin"));
+        assert_eq!(
+            header(&response, CROSS_ORIGIN_RESOURCE_POLICY),
+            Some("cross-origin")
+        );
+
+        let response = call(
+            &[DOCS],
+            test::TestRequest::get()
+                .uri("/api/v1/latest")
+                .insert_header((ORIGIN, "https://evil.example.com")),
+        )
+        .await;
+        assert_eq!(header(&response, ACCESS_CONTROL_ALLOW_ORIGIN), None);
+        assert_eq!(header(&response, VARY), Some("Origin"));
+    }
+
+    #[actix_web::test]
+    async fn applies_only_to_api_paths() {
+        let response = call(&[DOCS], preflight("/api", DOCS, "GET")).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let response = call(&[DOCS], preflight("/apis", DOCS, "GET")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = call(&[DOCS], preflight("/1.2.3", DOCS, "GET")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(header(&response, ACCESS_CONTROL_ALLOW_ORIGIN), None);
+
+        let response = call(
+            &[DOCS],
+            test::TestRequest::get()
+                .uri("/feed.atom")
+                .insert_header((ORIGIN, DOCS)),
+        )
+        .await;
+        assert_eq!(header(&response, ACCESS_CONTROL_ALLOW_ORIGIN), None);
+        assert_eq!(header(&response, CROSS_ORIGIN_RESOURCE_POLICY), None);
+    }
+}
@@ -0,0 +1,286 @@
+use crate::asset_manager::EncodedContent;
+use crate::config::Config;
+use crate::template::escape_html;
+use crate::utils::{create_encoded_response, format_datetime, get_cdn_url, parse_datetime};
+use crate::version_checker::{VersionChecker, VersionInfo};
+use actix_web::http::header::HttpDate;
+use actix_web::{HttpRequest, HttpResponse, Responder, get, web};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+const FEED_TITLE: &str = "highlight-it releases";
+
+/// A rendered feed and the state it was rendered from.
+struct CachedFeed {
+    updated_at: SystemTime,
+    content: Arc<EncodedContent>,
+}
+
+#[derive(Clone, Copy)]
+enum FeedFormat {
+    Atom,
+    Rss,
+}
+
+/// The public URL feeds link to and the feeds last rendered, built at
+/// startup so links never depend on the request's Host header.
+pub struct Feeds {
+    base_url: String,
+    atom: Mutex<Option<CachedFeed>>,
+    rss: Mutex<Option<CachedFeed>>,
+}
+
+impl Feeds {
+    /// Links to `base_url` if configured, otherwise to the listening address
+    /// with `scheme`.
+    pub fn new(config: &Config, scheme: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let base_url = match &config.base_url {
+            Some(base_url) => {
+                if !(base_url.starts_with("https://") || base_url.starts_with("http://"))
+                    || base_url
+                        .chars()
+                        .any(|c| c.is_whitespace() || c.is_control())
+                {
+                    return Err(format!("Invalid base URL: {}", base_url).into());
+                }
+                base_url.trim_end_matches('/').to_string()
+            }
+            None => format!("{}://{}", scheme, config.server_addr()),
+        };
+
+        Ok(Self {
+            base_url,
+            atom: Mutex::new(None),
+            rss: Mutex::new(None),
+        })
+    }
+}
+
+/// HTML describing where to load a version from, shared by both formats.
+fn entry_description(version_info: &VersionInfo) -> String {
+    let mut description = format!(
+        "<p>CDN URL: <code>{}</code></p><p>SRI hash: <code>{}</code></p>",
+        escape_html(&get_cdn_url(&version_info.version)),
+        escape_html(&version_info.sri_hash)
+    );
+
+    if let Some(message) = &version_info.deprecated {
+        description.push_str(&format!("<p>Deprecated: {}</p>", escape_html(message)));
+    }
+
+    description
+}
+
+fn render_atom(versions: &[VersionInfo], base_url: &str, updated_at: SystemTime) -> String {
+    let base_url = escape_html(base_url);
+    let mut feed = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <title>{FEED_TITLE}</title>
+  <id>{base_url}/feed.atom</id>
+  <link rel="self" type="application/atom+xml" href="{base_url}/feed.atom"/>
+  <link rel="alternate" type="text/html" href="{base_url}/"/>
+  <updated>{}</updated>
+"#,
+        format_datetime(updated_at)
+    );
+
+    for version_info in versions {
+        let version = escape_html(&version_info.version);
+        let published = version_info
+            .published
+            .as_deref()
+            .and_then(parse_datetime)
+            .unwrap_or(updated_at);
+
+        feed.push_str(&format!(
+            r#"  <entry>
+    <title>highlight-it {version}</title>
+    <id>{base_url}/{version}</id>
+    <link rel="alternate" type="text/html" href="{base_url}/{version}"/>
+    <published>{published}</published>
+    <updated>{published}</updated>
+    <author><name>highlight-it</name></author>
+    <content type="html">{}</content>
+  </entry>
+"#,
+            escape_html(&entry_description(version_info)),
+            published = format_datetime(published),
+        ));
+    }
+
+    feed.push_str("</feed>\n");
+    feed
+}
+
+fn render_rss(versions: &[VersionInfo], base_url: &str, updated_at: SystemTime) -> String {
+    let base_url = escape_html(base_url);
+    let mut feed = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0" xmlns:atom="http://www.w3.org/2005/Atom">
+  <channel>
+    <title>{FEED_TITLE}</title>
+    <link>{base_url}/</link>
+    <description>New versions of highlight-it with their CDN URLs and SRI hashes</description>
+    <atom:link rel="self" type="application/rss+xml" href="{base_url}/feed.rss"/>
+    <lastBuildDate>{}</lastBuildDate>
+"#,
+        HttpDate::from(updated_at)
+    );
+
+    for version_info in versions {
+        let version = escape_html(&version_info.version);
+
+        feed.push_str(&format!(
+            r#"    <item>
+      <title>highlight-it {version}</title>
+      <link>{base_url}/{version}</link>
+      <guid isPermaLink="true">{base_url}/{version}</guid>
+"#
+        ));
+        if let Some(published) = version_info.published.as_deref().and_then(parse_datetime) {
+            feed.push_str(&format!(
+                "      <pubDate>{}</pubDate>\n",
+                HttpDate::from(published)
+            ));
+        }
+        feed.push_str(&format!(
+            "      <description>{}</description>\n    </item>\n",
+            escape_html(&entry_description(version_info))
+        ));
+    }
+
+    feed.push_str("  </channel>\n</rss>\n");
+    feed
+}
+
+/// The feed for the current version state, rendered again only after the
+/// state changed.
+async fn feed(
+    feeds: &Feeds,
+    data: &VersionChecker,
+    format: FeedFormat,
+) -> (Arc<EncodedContent>, SystemTime) {
+    let updated_at = data.get_updated_at().await;
+    let cache = match format {
+        FeedFormat::Atom => &feeds.atom,
+        FeedFormat::Rss => &feeds.rss,
+    };
+
+    if let Some(cached) = cache.lock().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        if cached.updated_at == updated_at {
+            return (Arc::clone(&cached.content), updated_at);
+        }
+    }
+
+    let versions: Vec<VersionInfo> = data
+        .get_all_versions()
+        .await
+        .into_iter()
+        .filter(|v| !v.sri_hash.is_empty())
+        .collect();
+    let rendered = match format {
+        FeedFormat::Atom => render_atom(&versions, &feeds.base_url, updated_at),
+        FeedFormat::Rss => render_rss(&versions, &feeds.base_url, updated_at),
+    };
+    let content = Arc::new(EncodedContent::new(rendered.as_bytes()));
+
+    *cache.lock().unwrap_or_else(|e| e.into_inner()) = Some(CachedFeed {
+        updated_at,
+        content: Arc::clone(&content),
+    });
+
+    (content, updated_at)
+}
+
+#[get("/feed.atom")]
+pub async fn serve_atom_feed(
+    req: HttpRequest,
+    data: web::Data<VersionChecker>,
+    feeds: web::Data<Feeds>,
+) -> impl Responder {
+    let (content, updated_at) = feed(&feeds, &data, FeedFormat::Atom).await;
+
+    let builder = HttpResponse::Ok()
+        .content_type("application/atom+xml")
+        .append_header(("Cache-Control", "public, max-age=300"))
+        .take();
+
+    create_encoded_response(&req, builder, &content, updated_at)
+}
+
+#[get("/feed.rss")]
+pub async fn serve_rss_feed(
+    req: HttpRequest,
+    data: web::Data<VersionChecker>,
+    feeds: web::Data<Feeds>,
+) -> impl Responder {
+    let (content, updated_at) = feed(&feeds, &data, FeedFormat::Rss).await;
+
+    let builder = HttpResponse::Ok()
+        .content_type("application/rss+xml")
+        .append_header(("Cache-Control", "public, max-age=300"))
+        .take();
+
+    create_encoded_response(&req, builder, &content, updated_at)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::version_checker::sample_versions;
+    use std::time::Duration;
+
+    fn feeds(base_url: Option<&str>) -> Result<Feeds, Box<dyn std::error::Error>> {
+        let config = Config {
+            base_url: base_url.map(str::to_string),
+            ..Config::default()
+        };
+        Feeds::new(&config, "http")
+    }
+
+    #[test]
+    fn uses_the_configured_base_url() {
+        let config = Config::default();
+        let default = feeds(None).unwrap();
+        assert_eq!(default.base_url, format!("http://{}", config.server_addr()));
+
+        let configured = feeds(Some("https://demo.example.com/")).unwrap();
+        assert_eq!(configured.base_url, "https://demo.example.com");
+
+        for base_url in ["demo.example.com", "ftp://demo.example.com", "https://a b"] {
+            assert!(feeds(Some(base_url)).is_err(), "{}", base_url);
+        }
+    }
+
+    #[actix_web::test]
+    async fn links_to_the_base_url() {
+        let feeds = feeds(Some("https://demo.example.com")).unwrap();
+        let data = VersionChecker::with_versions(sample_versions(), &[]).await;
+
+        for format in [FeedFormat::Atom, FeedFormat::Rss] {
+            let (content, _) = feed(&feeds, &data, format).await;
+            let body = String::from_utf8(content.identity.to_vec()).unwrap();
+            assert!(body.contains("https://demo.example.com/1.2.3"), "{}", body);
+        }
+    }
+
+    #[actix_web::test]
+    async fn caches_until_the_state_changes() {
+        let feeds = feeds(None).unwrap();
+        let data = VersionChecker::with_versions(sample_versions(), &[]).await;
+
+        let (first, updated_at) = feed(&feeds, &data, FeedFormat::Atom).await;
+        let (second, _) = feed(&feeds, &data, FeedFormat::Atom).await;
+        assert!(Arc::ptr_eq(&first, &second));
+
+        let (rss, _) = feed(&feeds, &data, FeedFormat::Rss).await;
+        assert!(!Arc::ptr_eq(&first, &rss));
+
+        if let Some(cached) = feeds.atom.lock().unwrap().as_mut() {
+            cached.updated_at = updated_at - Duration::from_secs(1);
+        }
+        let (rendered, _) = feed(&feeds, &data, FeedFormat::Atom).await;
+        assert!(!Arc::ptr_eq(&first, &rendered));
+    }
+}
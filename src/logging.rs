@@ -0,0 +1,133 @@
+use crate::config::Config;
+use crate::metrics::NOT_FOUND_ROUTE;
+use actix_web::body::{BodySize, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::{Error, HttpMessage, HttpRequest};
+use std::io::{self, IsTerminal};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tracing::Instrument;
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest request ID accepted from a client or proxy
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+/// The ID of the current request, also sent in `X-Request-Id`
+#[derive(Clone)]
+struct RequestId(String);
+
+/// The ID of the current request, empty if it was not logged.
+pub fn request_id(req: &HttpRequest) -> String {
+    req.extensions()
+        .get::<RequestId>()
+        .map(|id| id.0.clone())
+        .unwrap_or_default()
+}
+
+/// Installs the global subscriber. `RUST_LOG` overrides the configured
+/// level, and the format is one of "text", "json" or "logfmt".
+pub fn init(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let filter =
+        EnvFilter::try_from_default_env().or_else(|_| EnvFilter::try_new(&config.log_level))?;
+    let registry = tracing_subscriber::registry().with(filter);
+
+    match config.log_format.as_str() {
+        "text" => registry
+            .with(tracing_subscriber::fmt::layer().with_ansi(io::stdout().is_terminal()))
+            .try_init()?,
+        "json" => registry
+            .with(tracing_subscriber::fmt::layer().json().flatten_event(true))
+            .try_init()?,
+        "logfmt" => registry.with(tracing_logfmt::layer()).try_init()?,
+        format => return Err(format!("Unknown log format: {}", format).into()),
+    }
+
+    Ok(())
+}
+
+/// Generates an ID unique to this process and unlikely to repeat across
+/// restarts, from the start time and a counter.
+fn generate_request_id() -> String {
+    static START: OnceLock<u64> = OnceLock::new();
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let start = START.get_or_init(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_micros() as u64)
+            .unwrap_or_default()
+    });
+
+    format!(
+        "{:012x}-{:08x}",
+        start & 0xffff_ffff_ffff,
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// Keeps the ID set by an upstream proxy if it is short and printable.
+fn incoming_request_id(req: &ServiceRequest) -> Option<String> {
+    req.headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| {
+            !id.is_empty()
+                && id.len() <= MAX_REQUEST_ID_LENGTH
+                && id
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-_.:".contains(c))
+        })
+        .map(str::to_string)
+}
+
+/// Middleware assigning each request an ID, running the handler inside a
+/// span carrying it, echoing it in `X-Request-Id` and writing an access log
+/// line once the response is ready.
+pub async fn log_request(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let request_id = incoming_request_id(&req).unwrap_or_else(generate_request_id);
+    req.extensions_mut().insert(RequestId(request_id.clone()));
+
+    let method = req.method().to_string();
+    let path = req.path().to_string();
+    let peer = req.connection_info().peer_addr().unwrap_or("-").to_string();
+    let span = tracing::info_span!("request", request_id = %request_id);
+    let start = Instant::now();
+
+    let mut response = next.call(req).instrument(span.clone()).await?;
+
+    let route = response.request().match_name().unwrap_or(NOT_FOUND_ROUTE);
+    let status = response.status().as_u16();
+    let bytes = match response.response().body().size() {
+        BodySize::Sized(bytes) => bytes.to_string(),
+        _ => "-".to_string(),
+    };
+
+    span.in_scope(|| {
+        tracing::info!(
+            target: "access",
+            %method,
+            %path,
+            route,
+            status,
+            latency_ms = start.elapsed().as_secs_f64() * 1000.0,
+            %bytes,
+            %peer,
+        );
+    });
+
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+
+    Ok(response)
+}
@@ -1,39 +1,80 @@
+use actix_web::dev::Server;
+use actix_web::http::{StatusCode, header};
 use actix_web::{App, HttpRequest, HttpResponse, HttpServer, Responder, get, middleware, web};
-use serde::Serialize;
+mod api;
 mod asset_manager;
+mod badge;
 mod config;
+mod cors;
+mod feed;
+mod logging;
+mod metrics;
+mod openapi;
+mod rate_limit;
+mod security;
+mod snippet;
+mod socket;
+mod systemd;
+mod template;
+mod tls;
 mod utils;
 mod version_checker;
+mod version_range;
 
-use asset_manager::{AssetManager, AssetType};
+use asset_manager::AssetManager;
+use badge::BadgeRenderer;
 use config::{Config, load_config};
+use cors::CorsPolicy;
+use feed::Feeds;
+use metrics::Metrics;
+use openapi::{API_DOCS_TEMPLATE, api_docs_context};
+use rate_limit::RateLimiter;
+use security::SecurityPolicy;
+use socket::Listener;
 use std::process::Command;
+use std::sync::Arc;
+use template::TemplateContext;
+use tls::CertificateResolver;
+use tracing::{error, info, warn};
 use utils::{
-    create_not_found_response, get_cdn_url, get_current_datetime, get_hit_demo_version,
-    not_found_handler,
+    NOT_FOUND_TEMPLATE, PageFormat, create_encoded_response, create_not_found_response,
+    create_not_ready_response, create_page_response, get_current_datetime, get_hit_demo_version,
+    negotiate_page_format, not_found_context, not_found_handler,
 };
 use version_checker::{VersionChecker, get_versions_selector, is_valid_version};
 
-#[derive(Serialize)]
-struct VersionResponse {
+const IMMUTABLE_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+const INDEX_TEMPLATE: &str = "index.min.html";
+
+/// Values of the index template, also used to validate it at startup.
+fn index_context(
+    versions_selector: String,
     version: String,
     sri_hash: String,
-    url: String,
-}
-
-#[derive(Serialize)]
-struct VersionsResponse {
-    versions: Vec<VersionResponse>,
-    latest: VersionResponse,
+    csp_nonce: String,
+) -> TemplateContext {
+    let mut context = TemplateContext::new();
+    context
+        .insert("demo_version", get_hit_demo_version())
+        .insert_html("versions_selector", versions_selector)
+        .insert("version", version)
+        .insert("sri_hash", sri_hash)
+        .insert("csp_nonce", csp_nonce);
+    context
 }
 
 #[get("/")]
 async fn serve_index(
+    req: HttpRequest,
     version_checker: web::Data<VersionChecker>,
     asset_manager: web::Data<AssetManager>,
 ) -> impl Responder {
-    if let Some(content) = asset_manager.get_template("index.min.html").await {
+    if let Some(template) = asset_manager.get_template(INDEX_TEMPLATE).await {
         let version_info = version_checker.get_current_version_info().await;
+        if version_info.version.is_empty() || version_info.sri_hash.is_empty() {
+            return create_not_ready_response();
+        }
         let version = version_info.version;
         let sri_hash = version_info.sri_hash;
 
@@ -41,18 +82,32 @@ async fn serve_index(
 
         let versions_html = get_versions_selector(all_versions, version.clone(), None);
 
-        let content = content
-            .replace("DEMO_VERSION", &get_hit_demo_version())
-            .replace("VERSION_SELECTOR", &versions_html)
-            .replace("VERSION", &version)
-            .replace("SRI_HASH", &sri_hash);
+        let content = match template.compiled.render(&index_context(
+            versions_html,
+            version,
+            sri_hash,
+            security::request_nonce(&req),
+        )) {
+            Ok(content) => content,
+            Err(e) => {
+                error!(template = INDEX_TEMPLATE, error = %e, "Template rendering failed");
+                return HttpResponse::InternalServerError().body("Template rendering failed");
+            }
+        };
 
-        HttpResponse::Ok()
+        let last_modified = template
+            .modified
+            .max(version_checker.get_updated_at().await);
+
+        let builder = HttpResponse::Ok()
             .content_type("text/html")
             .append_header(("Cache-Control", "public, max-age=60"))
-            .body(content)
+            .take();
+
+        create_page_response(&req, builder, &asset_manager, content, last_modified).await
     } else {
         create_not_found_response(
+            &req,
             "Template not found",
             version_checker,
             asset_manager,
@@ -62,16 +117,45 @@ async fn serve_index(
     }
 }
 
+/// A version's demo page, or its metadata as JSON or Markdown for clients
+/// asking for those in `Accept`.
 #[get("/{version}")]
 async fn serve_versioned_index(
+    req: HttpRequest,
     path: web::Path<String>,
     data: web::Data<VersionChecker>,
     asset_manager: web::Data<AssetManager>,
 ) -> impl Responder {
-    let version = path.into_inner();
+    let mut response = match negotiate_page_format(&req) {
+        Some(format) => versioned_page(&req, path.into_inner(), format, data, asset_manager).await,
+        None => HttpResponse::NotAcceptable()
+            .content_type("text/plain")
+            .body("Available as text/html, application/json and text/markdown\n"),
+    };
+
+    response
+        .headers_mut()
+        .append(header::VARY, header::HeaderValue::from_static("Accept"));
+    response
+}
 
+async fn versioned_page(
+    req: &HttpRequest,
+    version: String,
+    format: PageFormat,
+    data: web::Data<VersionChecker>,
+    asset_manager: web::Data<AssetManager>,
+) -> HttpResponse {
     if !is_valid_version(&version) {
+        if format == PageFormat::Json {
+            return api::error_response(
+                req,
+                StatusCode::BAD_REQUEST,
+                "Expected a version number such as 0.3.2",
+            );
+        }
         return create_not_found_response(
+            req,
             "Invalid version",
             data,
             asset_manager,
@@ -82,61 +166,102 @@ async fn serve_versioned_index(
 
     let all_versions = data.get_all_versions().await;
 
-    if let Some(version_info) = all_versions.iter().find(|v| v.version == version) {
-        if let Some(content) = asset_manager.get_template("index.min.html").await {
-            let latest_version_info = data.get_current_version_info().await;
-            let latest_version = latest_version_info.version.clone();
-
-            let versions_html =
-                get_versions_selector(all_versions.clone(), latest_version, Some(version.clone()));
-
-            let content = content
-                .replace("DEMO_VERSION", &get_hit_demo_version())
-                .replace("VERSION_SELECTOR", &versions_html)
-                .replace("VERSION", &version_info.version)
-                .replace("SRI_HASH", &version_info.sri_hash);
-
-            return HttpResponse::Ok()
-                .content_type("text/html")
-                .append_header(("Cache-Control", "public, max-age=3600"))
-                .body(content);
-        } else {
-            return HttpResponse::InternalServerError().body("Template not found");
+    let Some(version_info) = all_versions.iter().find(|v| v.version == version) else {
+        if format == PageFormat::Json {
+            return api::error_response(
+                req,
+                StatusCode::NOT_FOUND,
+                &format!("Version {} is not available", version),
+            );
         }
-    } else {
         return create_not_found_response(
+            req,
             "Version not found",
             data,
             asset_manager,
             Some(&format!("/{}", version)),
         )
         .await;
+    };
+
+    match format {
+        PageFormat::Json => {
+            return HttpResponse::Ok()
+                .append_header(("Cache-Control", "public, max-age=60"))
+                .json(api::version_detail(&data, version_info.clone()).await);
+        }
+        PageFormat::Markdown => {
+            return HttpResponse::Ok()
+                .content_type("text/markdown; charset=utf-8")
+                .append_header(("Cache-Control", "public, max-age=60"))
+                .body(snippet::markdown_page(
+                    &version_info.version,
+                    &version_info.sri_hash,
+                    version_info.deprecated.as_deref(),
+                ));
+        }
+        PageFormat::Html => {}
     }
+
+    let Some(template) = asset_manager.get_template(INDEX_TEMPLATE).await else {
+        return HttpResponse::InternalServerError().body("Template not found");
+    };
+
+    let latest_version_info = data.get_current_version_info().await;
+    let latest_version = latest_version_info.version.clone();
+
+    let versions_html =
+        get_versions_selector(all_versions.clone(), latest_version, Some(version.clone()));
+
+    let content = match template.compiled.render(&index_context(
+        versions_html,
+        version_info.version.clone(),
+        version_info.sri_hash.clone(),
+        security::request_nonce(req),
+    )) {
+        Ok(content) => content,
+        Err(e) => {
+            error!(template = INDEX_TEMPLATE, error = %e, "Template rendering failed");
+            return HttpResponse::InternalServerError().body("Template rendering failed");
+        }
+    };
+
+    let last_modified = template.modified.max(data.get_updated_at().await);
+
+    let builder = HttpResponse::Ok()
+        .content_type("text/html")
+        .append_header(("Cache-Control", "public, max-age=3600"))
+        .take();
+
+    create_page_response(req, builder, &asset_manager, content, last_modified).await
 }
 
 #[get("/static/{filename:.*}")]
 async fn serve_static(
+    req: HttpRequest,
     path: web::Path<String>,
     asset_manager: web::Data<AssetManager>,
     version_checker: web::Data<VersionChecker>,
 ) -> impl Responder {
     let filename = path.into_inner();
 
-    if filename.ends_with(".min.js") || filename.ends_with(".min.css") {
-        if let Some(asset) = asset_manager.get_asset(&filename).await {
-            let content_type = match asset.asset_type {
-                AssetType::JavaScript => "application/javascript",
-                AssetType::CSS => "text/css",
-            };
+    if let Some(asset) = asset_manager.get_asset(&filename).await {
+        let cache_control = if filename == asset.fingerprinted_name {
+            IMMUTABLE_CACHE_CONTROL
+        } else {
+            "public, max-age=60"
+        };
 
-            return HttpResponse::Ok()
-                .content_type(content_type)
-                .append_header(("Cache-Control", "public, max-age=60"))
-                .body(asset.content);
-        }
+        let builder = HttpResponse::Ok()
+            .content_type(asset.asset_type.mime_type)
+            .append_header(("Cache-Control", cache_control))
+            .take();
+
+        return create_encoded_response(&req, builder, &asset.encoded, asset.modified);
     }
 
     create_not_found_response(
+        &req,
         "Asset not found",
         version_checker,
         asset_manager,
@@ -145,8 +270,9 @@ async fn serve_static(
     .await
 }
 
-#[get("/static/{version}/{filename:.*}")]
+#[get("/static/{version:[0-9]+\\.[0-9]+\\.[0-9]+}/{filename:.*}")]
 async fn serve_versioned_static(
+    req: HttpRequest,
     path: web::Path<(String, String)>,
     asset_manager: web::Data<AssetManager>,
     version_checker: web::Data<VersionChecker>,
@@ -155,6 +281,7 @@ async fn serve_versioned_static(
 
     if !is_valid_version(&version) {
         return create_not_found_response(
+            &req,
             "Invalid version",
             version_checker,
             asset_manager,
@@ -168,6 +295,7 @@ async fn serve_versioned_static(
 
     if !version_exists {
         return create_not_found_response(
+            &req,
             "Version not found",
             version_checker,
             asset_manager,
@@ -177,20 +305,24 @@ async fn serve_versioned_static(
     }
 
     if let Some(asset) = asset_manager.get_asset(&filename).await {
-        let content_type = match asset.asset_type {
-            AssetType::JavaScript => "application/javascript",
-            AssetType::CSS => "text/css",
+        let encoded = asset_manager.encode_versioned(&version, &asset).await;
+
+        let cache_control = if filename == asset.fingerprinted_name {
+            IMMUTABLE_CACHE_CONTROL
+        } else {
+            "public, max-age=3600"
         };
 
-        let content = asset.content.replace("VERSION", &version);
+        let builder = HttpResponse::Ok()
+            .content_type(asset.asset_type.mime_type)
+            .append_header(("Cache-Control", cache_control))
+            .take();
 
-        return HttpResponse::Ok()
-            .content_type(content_type)
-            .append_header(("Cache-Control", "public, max-age=3600"))
-            .body(content);
+        return create_encoded_response(&req, builder, &encoded, asset.modified);
     }
 
     create_not_found_response(
+        &req,
         "Asset not found",
         version_checker,
         asset_manager,
@@ -199,42 +331,44 @@ async fn serve_versioned_static(
     .await
 }
 
-#[get("/api/latest")]
-async fn serve_latest_version_api(data: web::Data<VersionChecker>) -> impl Responder {
-    let version_info = data.get_current_version_info().await;
+#[get("/healthz")]
+async fn serve_healthz() -> impl Responder {
     HttpResponse::Ok()
-        .append_header(("Cache-Control", "public, max-age=60"))
-        .json(VersionResponse {
-            version: version_info.version.clone(),
-            sri_hash: version_info.sri_hash,
-            url: get_cdn_url(&version_info.version),
-        })
+        .content_type("text/plain")
+        .append_header(("Cache-Control", "no-store"))
+        .body("ok\n")
 }
 
-#[get("/api/versions")]
-async fn serve_all_versions_api(data: web::Data<VersionChecker>) -> impl Responder {
-    let all_versions = data.get_all_versions().await;
-    let latest_version = data.get_latest_version_info().await;
+#[get("/readyz")]
+async fn serve_readyz(
+    version_checker: web::Data<VersionChecker>,
+    asset_manager: web::Data<AssetManager>,
+) -> impl Responder {
+    let mut failures = Vec::new();
 
-    let versions_response: Vec<VersionResponse> = all_versions
-        .into_iter()
-        .map(|v| VersionResponse {
-            version: v.version.clone(),
-            sri_hash: v.sri_hash,
-            url: get_cdn_url(&v.version),
-        })
-        .collect();
+    if !version_checker.has_servable_version().await {
+        failures.push("no version with an SRI hash is available");
+    }
+    if asset_manager.get_template(INDEX_TEMPLATE).await.is_none() {
+        failures.push("index template is not loaded");
+    }
 
-    HttpResponse::Ok()
-        .append_header(("Cache-Control", "public, max-age=60"))
-        .json(VersionsResponse {
-            versions: versions_response,
-            latest: VersionResponse {
-                version: latest_version.version.clone(),
-                sri_hash: latest_version.sri_hash,
-                url: get_cdn_url(&latest_version.version),
-            },
-        })
+    let mut builder = if failures.is_empty() {
+        HttpResponse::Ok()
+    } else {
+        HttpResponse::ServiceUnavailable()
+    };
+
+    let body = if failures.is_empty() {
+        "ready\n".to_string()
+    } else {
+        format!("not ready: {}\n", failures.join(", "))
+    };
+
+    builder
+        .content_type("text/plain")
+        .append_header(("Cache-Control", "no-store"))
+        .body(body)
 }
 
 #[get("/sitemap.xml")]
@@ -242,8 +376,10 @@ async fn serve_sitemap(
     req: HttpRequest,
     version_checker: web::Data<VersionChecker>,
 ) -> impl Responder {
-    let connection_info = req.connection_info();
-    let base_url = format!("{}://{}", connection_info.scheme(), connection_info.host());
+    let base_url = {
+        let connection_info = req.connection_info();
+        format!("{}://{}", connection_info.scheme(), connection_info.host())
+    };
 
     let current_datetime = get_current_datetime();
 
@@ -289,83 +425,377 @@ async fn serve_sitemap(
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    println!("Running build script to minify assets...");
+    let (config, config_error) = match load_config() {
+        Ok(config) => (config, None),
+        Err(e) => (Config::default(), Some(e.to_string())),
+    };
+
+    if let Err(e) = logging::init(&config) {
+        eprintln!("Failed to initialize logging: {}", e);
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            e.to_string(),
+        ));
+    }
+
+    if let Some(e) = config_error {
+        error!(error = %e, "Error loading configuration, using defaults");
+    }
+
+    info!("Running build script to minify assets");
     match Command::new("node").arg("build.js").status() {
         Ok(status) => {
             if !status.success() {
-                eprintln!("Warning: Build script failed with exit code: {}", status);
+                warn!(%status, "Build script failed");
             } else {
-                println!("Build script completed successfully");
+                info!("Build script completed successfully");
             }
         }
         Err(e) => {
-            eprintln!("Warning: Failed to run build script: {}", e);
+            warn!(error = %e, "Failed to run build script");
         }
     }
 
-    let config = load_config().unwrap_or_else(|e| {
-        eprintln!("Error loading configuration: {}", e);
-        Config::default()
-    });
-
-    println!("HTTP timeout: {} seconds", config.http_timeout);
-    println!(
-        "Version check interval: {} seconds",
-        config.version_check_interval
+    info!(
+        http_timeout = config.http_timeout,
+        version_check_interval = config.version_check_interval,
+        "Loaded configuration"
     );
 
+    let metrics = Metrics::new();
+
     let checker = VersionChecker::new(
         "highlight-it",
         config.http_timeout,
         config.version_check_interval,
         config.cache_dir.as_deref(),
+        metrics.clone(),
     );
     checker.start_checking().await;
 
-    let asset_manager = match AssetManager::new().await {
+    let asset_manager = match AssetManager::new(&config.asset_roots, &config.template_roots).await {
         Ok(manager) => manager,
         Err(e) => {
-            eprintln!("Failed to initialize asset manager: {}", e);
+            error!(error = %e, "Failed to initialize asset manager");
             return Err(e);
         }
     };
 
+    // Encode the versioned assets now rather than on the first request for
+    // each version, and again whenever new versions are found
+    let mut version_updates = checker.subscribe();
+    let versions: Vec<String> = checker
+        .get_all_versions()
+        .await
+        .into_iter()
+        .map(|v| v.version)
+        .collect();
+    asset_manager.prepare_versions(&versions).await;
+    {
+        let checker = checker.clone();
+        let asset_manager = asset_manager.clone();
+        tokio::spawn(async move {
+            while version_updates.changed().await.is_ok() {
+                let versions: Vec<String> = checker
+                    .get_all_versions()
+                    .await
+                    .into_iter()
+                    .map(|v| v.version)
+                    .collect();
+                asset_manager.prepare_versions(&versions).await;
+            }
+        });
+    }
+
+    for (filename, context) in [
+        (
+            INDEX_TEMPLATE,
+            index_context(String::new(), String::new(), String::new(), String::new()),
+        ),
+        (
+            NOT_FOUND_TEMPLATE,
+            not_found_context(String::new(), String::new(), String::new(), String::new()),
+        ),
+        (API_DOCS_TEMPLATE, api_docs_context(String::new())),
+    ] {
+        if let Some(template) = asset_manager.get_template(filename).await {
+            if let Err(e) = template.compiled.validate(&context) {
+                error!(template = filename, error = %e, "Invalid template");
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e));
+            }
+        }
+    }
+
+    let tls_config = match config.tls_paths() {
+        Ok(Some((cert_path, key_path))) => {
+            let resolver = Arc::new(CertificateResolver::new(cert_path, key_path)?);
+            tls::reload_on_hangup(Arc::clone(&resolver))?;
+            Some(tls::build_server_config(resolver)?)
+        }
+        Ok(None) => None,
+        Err(e) => {
+            error!(error = %e, "Invalid TLS configuration");
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                e.to_string(),
+            ));
+        }
+    };
+
+    let security_policy = match SecurityPolicy::new(&config.security_headers) {
+        Ok(policy) => web::Data::new(policy),
+        Err(e) => {
+            error!(error = %e, "Invalid security headers");
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                e.to_string(),
+            ));
+        }
+    };
+
+    let cors_policy = match CorsPolicy::new(&config.cors) {
+        Ok(policy) => policy.map(web::Data::new),
+        Err(e) => {
+            error!(error = %e, "Invalid CORS configuration");
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                e.to_string(),
+            ));
+        }
+    };
+
+    let badge_renderer = match BadgeRenderer::new(&config.badge) {
+        Ok(renderer) => web::Data::new(renderer),
+        Err(e) => {
+            error!(error = %e, "Invalid badge configuration");
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                e.to_string(),
+            ));
+        }
+    };
+
     let app_config = web::Data::new(config.clone());
+    let redirect_config = app_config.clone();
+    let notify_checker = checker.clone();
+    let notify_asset_manager = asset_manager.clone();
+    let metrics_data = web::Data::new(metrics);
+    let metrics_server_data = metrics_data.clone();
+    let metrics_checker = checker.clone();
+    let metrics_config = app_config.clone();
+    let metrics_on_main = config.metrics_addr.is_none();
+    let rate_limiter = web::Data::new(RateLimiter::new(
+        config.rate_limits.clone(),
+        config.trusted_proxies.clone(),
+    ));
+    let scheme = if tls_config.is_some() {
+        "https"
+    } else {
+        "http"
+    };
 
-    println!(
-        "Starting server on http://{}:{} with {} workers",
-        config.host, config.port, config.workers
-    );
+    let feeds = match Feeds::new(&config, scheme) {
+        Ok(feeds) => web::Data::new(feeds),
+        Err(e) => {
+            error!(error = %e, "Invalid base URL");
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                e.to_string(),
+            ));
+        }
+    };
 
-    HttpServer::new(move || {
+    let server = HttpServer::new(move || {
         App::new()
+            .wrap(middleware::from_fn(api::json_errors))
             .wrap(middleware::Compress::default())
-            .wrap(
-                middleware::DefaultHeaders::new()
-                    .add(("Strict-Transport-Security", "max-age=31536000; includeSubDomains"))
-                    .add(("Referrer-Policy", "strict-origin-when-cross-origin"))
-                    .add(("X-Content-Type-Options", "nosniff"))
-                    .add(("X-Frame-Options", "DENY"))
-                    .add(("Content-Security-Policy", "default-src 'self'; script-src 'self' 'unsafe-inline' https://cdn.jsdelivr.net; style-src 'self' 'unsafe-inline' https://cdn.jsdelivr.net; connect-src 'self'; font-src 'self' data:; img-src 'self' data:; frame-ancestors 'none'"))
-                    .add(("Cross-Origin-Embedder-Policy", "require-corp"))
-                    .add(("Cross-Origin-Opener-Policy", "same-origin"))
-                    .add(("Cross-Origin-Resource-Policy", "same-origin"))
-                    .add(("Permissions-Policy", "camera=(), microphone=(), geolocation=()"))
-            )
+            .wrap(middleware::from_fn(cors::handle_cors))
+            .wrap(middleware::from_fn(security::apply_security_headers))
+            .wrap(middleware::from_fn(rate_limit::limit_request))
+            .wrap(middleware::from_fn(metrics::record_request))
+            .wrap(middleware::from_fn(logging::log_request))
             .app_data(web::Data::new(checker.clone()))
             .app_data(web::Data::new(asset_manager.clone()))
             .app_data(app_config.clone())
+            .app_data(metrics_data.clone())
+            .app_data(rate_limiter.clone())
+            .app_data(security_policy.clone())
+            .app_data(badge_renderer.clone())
+            .app_data(feeds.clone())
+            .configure(|cfg| {
+                if let Some(cors_policy) = &cors_policy {
+                    cfg.app_data(cors_policy.clone());
+                }
+                if metrics_on_main {
+                    cfg.service(metrics::serve_metrics);
+                }
+            })
             .service(serve_index)
             .service(serve_sitemap)
-            .service(serve_latest_version_api)
-            .service(serve_all_versions_api)
+            .service(feed::serve_atom_feed)
+            .service(feed::serve_rss_feed)
+            .service(serve_healthz)
+            .service(serve_readyz)
+            .service(badge::serve_badge)
+            .configure(api::configure)
             .service(serve_versioned_static)
             .service(serve_static)
             .service(serve_versioned_index)
             .default_service(web::route().to(not_found_handler))
     })
-    .bind(config.server_addr())?
     .workers(config.workers)
-    .run()
-    .await
+    .shutdown_timeout(config.shutdown_timeout)
+    .disable_signals();
+
+    let activated = socket::activated_listeners()?;
+
+    let listeners = if !activated.is_empty() {
+        info!(
+            sockets = activated.len(),
+            workers = config.workers,
+            "Starting server on systemd sockets"
+        );
+        activated
+    } else if let Some(path) = &config.unix_socket {
+        info!(
+            socket = %path,
+            workers = config.workers,
+            "Starting server on Unix socket"
+        );
+
+        vec![socket::bind_unix_socket(
+            path,
+            config.unix_socket_mode.as_deref(),
+            config.unix_socket_owner.as_deref(),
+        )?]
+    } else {
+        Vec::new()
+    };
+
+    let server = if !listeners.is_empty() {
+        let mut server = server;
+        for listener in listeners {
+            server = match (listener, &tls_config) {
+                (Listener::Tcp(listener), Some(tls_config)) => {
+                    server.listen_rustls_0_23(listener, tls_config.clone())?
+                }
+                (Listener::Tcp(listener), None) => server.listen(listener)?,
+                #[cfg(unix)]
+                (Listener::Unix(listener), _) => server.listen_uds(listener)?,
+            };
+        }
+        server
+    } else {
+        info!(
+            address = %format!("{}://{}:{}", scheme, config.host, config.port),
+            workers = config.workers,
+            "Starting server"
+        );
+
+        match tls_config {
+            Some(tls_config) => server.bind_rustls_0_23(config.server_addr(), tls_config)?,
+            None => server.bind(config.server_addr())?,
+        }
+    }
+    .run();
+
+    let mut handles = vec![server.handle()];
+
+    let redirect_server = match config.redirect_addr() {
+        Some(redirect_addr) if scheme == "https" => {
+            info!(address = %redirect_addr, "Redirecting HTTP to HTTPS");
+
+            let redirect_server = HttpServer::new(move || {
+                App::new()
+                    .app_data(redirect_config.clone())
+                    .default_service(web::route().to(tls::redirect_to_https))
+            })
+            .workers(1)
+            .disable_signals()
+            .bind(redirect_addr)?
+            .run();
+
+            handles.push(redirect_server.handle());
+            Some(redirect_server)
+        }
+        _ => None,
+    };
+
+    let metrics_server = match &config.metrics_addr {
+        Some(metrics_addr) => {
+            info!(address = %metrics_addr, "Serving metrics");
+
+            let metrics_server = HttpServer::new(move || {
+                App::new()
+                    .app_data(web::Data::new(metrics_checker.clone()))
+                    .app_data(metrics_config.clone())
+                    .app_data(metrics_server_data.clone())
+                    .service(metrics::serve_metrics)
+            })
+            .workers(1)
+            .disable_signals()
+            .bind(metrics_addr)?
+            .run();
+
+            handles.push(metrics_server.handle());
+            Some(metrics_server)
+        }
+        None => None,
+    };
+
+    systemd::shutdown_on_terminate(handles)?;
+    systemd::notify_ready(notify_checker, notify_asset_manager).await;
+
+    tokio::try_join!(
+        server,
+        run_optional(redirect_server),
+        run_optional(metrics_server)
+    )
+    .map(|_| ())
+}
+
+/// Runs an auxiliary server if it is configured.
+async fn run_optional(server: Option<Server>) -> std::io::Result<()> {
+    match server {
+        Some(server) => server.await,
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::version_checker::sample_versions;
+    use actix_web::http::StatusCode;
+    use actix_web::http::header::{CACHE_CONTROL, RETRY_AFTER};
+    use actix_web::test::{TestRequest, call_service, init_service};
+
+    #[actix_web::test]
+    async fn index_is_unavailable_until_a_version_is_loaded() {
+        let asset_manager = AssetManager::new(&[], &["templates".to_string()])
+            .await
+            .unwrap();
+
+        for (versions, status) in [
+            (Vec::new(), StatusCode::SERVICE_UNAVAILABLE),
+            (sample_versions(), StatusCode::OK),
+        ] {
+            let app = init_service(
+                App::new()
+                    .app_data(web::Data::new(
+                        VersionChecker::with_versions(versions, &[]).await,
+                    ))
+                    .app_data(web::Data::new(asset_manager.clone()))
+                    .service(serve_index),
+            )
+            .await;
+
+            let response = call_service(&app, TestRequest::get().uri("/").to_request()).await;
+            assert_eq!(response.status(), status);
+            if status == StatusCode::SERVICE_UNAVAILABLE {
+                assert_eq!(response.headers().get(RETRY_AFTER).unwrap(), "10");
+                assert_eq!(response.headers().get(CACHE_CONTROL).unwrap(), "no-store");
+            }
+        }
+    }
 }
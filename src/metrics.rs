@@ -0,0 +1,507 @@
+use crate::config::Config;
+use crate::version_checker::VersionChecker;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::middleware::Next;
+use actix_web::{Error, HttpRequest, HttpResponse, Responder, get, web};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Upper bounds in seconds of the latency histogram buckets
+const DURATION_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Route label for requests that matched no handler
+pub const NOT_FOUND_ROUTE: &str = "not_found";
+
+/// Escapes a label value as the text exposition format requires.
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[derive(Clone, Default)]
+struct Histogram {
+    buckets: [u64; DURATION_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+
+        for (bucket, bound) in self.buckets.iter_mut().zip(DURATION_BUCKETS) {
+            if seconds <= bound {
+                *bucket += 1;
+            }
+        }
+
+        self.sum += seconds;
+        self.count += 1;
+    }
+
+    fn write(&self, output: &mut String, name: &str, labels: &str) {
+        let separator = if labels.is_empty() { "" } else { "," };
+
+        for (bucket, bound) in self.buckets.iter().zip(DURATION_BUCKETS) {
+            let _ = writeln!(
+                output,
+                "{}_bucket{{{}{}le=\"{}\"}} {}",
+                name, labels, separator, bound, bucket
+            );
+        }
+
+        let _ = writeln!(
+            output,
+            "{}_bucket{{{}{}le=\"+Inf\"}} {}",
+            name, labels, separator, self.count
+        );
+        let labels = if labels.is_empty() {
+            String::new()
+        } else {
+            format!("{{{}}}", labels)
+        };
+        let _ = writeln!(output, "{}_sum{} {}", name, labels, self.sum);
+        let _ = writeln!(output, "{}_count{} {}", name, labels, self.count);
+    }
+}
+
+#[derive(Default)]
+struct Registry {
+    requests: BTreeMap<(String, u16), u64>,
+    request_durations: BTreeMap<String, Histogram>,
+    version_checks: u64,
+    version_check_failures: u64,
+    sri_download_bytes: u64,
+    sri_download_durations: Histogram,
+    last_successful_check: Option<SystemTime>,
+}
+
+/// Counters and histograms exposed at `/metrics` in the Prometheus text
+/// format. Clones share the same registry.
+#[derive(Clone, Default)]
+pub struct Metrics {
+    registry: Arc<Mutex<Registry>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn update(&self, f: impl FnOnce(&mut Registry)) {
+        if let Ok(mut registry) = self.registry.lock() {
+            f(&mut registry);
+        }
+    }
+
+    pub fn observe_request(&self, route: &str, status: u16, duration: Duration) {
+        self.update(|registry| {
+            *registry
+                .requests
+                .entry((route.to_string(), status))
+                .or_default() += 1;
+            registry
+                .request_durations
+                .entry(route.to_string())
+                .or_default()
+                .observe(duration);
+        });
+    }
+
+    /// Records the outcome of one request to the upstream package registry.
+    pub fn observe_version_check(&self, success: bool) {
+        self.update(|registry| {
+            registry.version_checks += 1;
+            if success {
+                registry.last_successful_check = Some(SystemTime::now());
+            } else {
+                registry.version_check_failures += 1;
+            }
+        });
+    }
+
+    pub fn observe_sri_download(&self, bytes: usize, duration: Duration) {
+        self.update(|registry| {
+            registry.sri_download_bytes += bytes as u64;
+            registry.sri_download_durations.observe(duration);
+        });
+    }
+
+    pub fn render(&self, versions_cached: usize) -> String {
+        let mut output = String::new();
+
+        let Ok(registry) = self.registry.lock() else {
+            return output;
+        };
+
+        output.push_str("# HELP hit_demo_http_requests_total HTTP requests by route and status.\n");
+        output.push_str("# TYPE hit_demo_http_requests_total counter\n");
+        for ((route, status), count) in &registry.requests {
+            let _ = writeln!(
+                output,
+                "hit_demo_http_requests_total{{route=\"{}\",status=\"{}\"}} {}",
+                escape_label_value(route),
+                status,
+                count
+            );
+        }
+
+        output.push_str(
+            "# HELP hit_demo_http_request_duration_seconds HTTP request latency by route.\n",
+        );
+        output.push_str("# TYPE hit_demo_http_request_duration_seconds histogram\n");
+        for (route, histogram) in &registry.request_durations {
+            histogram.write(
+                &mut output,
+                "hit_demo_http_request_duration_seconds",
+                &format!("route=\"{}\"", escape_label_value(route)),
+            );
+        }
+
+        output
+            .push_str("# HELP hit_demo_version_checks_total Upstream version checks attempted.\n");
+        output.push_str("# TYPE hit_demo_version_checks_total counter\n");
+        let _ = writeln!(
+            output,
+            "hit_demo_version_checks_total {}",
+            registry.version_checks
+        );
+
+        output.push_str(
+            "# HELP hit_demo_version_check_failures_total Upstream version checks that failed.\n",
+        );
+        output.push_str("# TYPE hit_demo_version_check_failures_total counter\n");
+        let _ = writeln!(
+            output,
+            "hit_demo_version_check_failures_total {}",
+            registry.version_check_failures
+        );
+
+        output.push_str(
+            "# HELP hit_demo_sri_download_bytes_total Bytes downloaded to compute SRI hashes.\n",
+        );
+        output.push_str("# TYPE hit_demo_sri_download_bytes_total counter\n");
+        let _ = writeln!(
+            output,
+            "hit_demo_sri_download_bytes_total {}",
+            registry.sri_download_bytes
+        );
+
+        output.push_str(
+            "# HELP hit_demo_sri_download_duration_seconds Time to download a script for its SRI hash.\n",
+        );
+        output.push_str("# TYPE hit_demo_sri_download_duration_seconds histogram\n");
+        registry.sri_download_durations.write(
+            &mut output,
+            "hit_demo_sri_download_duration_seconds",
+            "",
+        );
+
+        output.push_str("# HELP hit_demo_versions_cached Versions in the version cache.\n");
+        output.push_str("# TYPE hit_demo_versions_cached gauge\n");
+        let _ = writeln!(output, "hit_demo_versions_cached {}", versions_cached);
+
+        if let Some(last_successful_check) = registry.last_successful_check {
+            let age = last_successful_check.elapsed().unwrap_or_default();
+
+            output.push_str(
+                "# HELP hit_demo_last_successful_check_age_seconds Seconds since the last successful upstream check.\n",
+            );
+            output.push_str("# TYPE hit_demo_last_successful_check_age_seconds gauge\n");
+            let _ = writeln!(
+                output,
+                "hit_demo_last_successful_check_age_seconds {}",
+                age.as_secs_f64()
+            );
+        }
+
+        output
+    }
+}
+
+/// Middleware recording the count and latency of every request, labelled
+/// with the name of the handler that served it.
+pub async fn record_request(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let metrics = req.app_data::<web::Data<Metrics>>().cloned();
+    let start = Instant::now();
+
+    let response = next.call(req).await?;
+
+    if let Some(metrics) = metrics {
+        let route = response.request().match_name().unwrap_or(NOT_FOUND_ROUTE);
+        metrics.observe_request(route, response.status().as_u16(), start.elapsed());
+    }
+
+    Ok(response)
+}
+
+/// Compares in constant time so the token cannot be guessed byte by byte.
+fn token_matches(provided: &[u8], expected: &[u8]) -> bool {
+    provided.len() == expected.len()
+        && provided
+            .iter()
+            .zip(expected)
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+#[get("/metrics")]
+pub async fn serve_metrics(
+    req: HttpRequest,
+    metrics: web::Data<Metrics>,
+    version_checker: web::Data<VersionChecker>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    if let Some(token) = &config.metrics_token {
+        let authorized = req
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|provided| token_matches(provided.as_bytes(), token.as_bytes()));
+
+        if !authorized {
+            return HttpResponse::Unauthorized()
+                .append_header(("WWW-Authenticate", "Bearer"))
+                .finish();
+        }
+    }
+
+    let versions_cached = version_checker.get_all_versions().await.len();
+
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .append_header(("Cache-Control", "no-store"))
+        .body(metrics.render(versions_cached))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::version_checker::sample_versions;
+    use actix_web::http::StatusCode;
+    use actix_web::http::header::WWW_AUTHENTICATE;
+    use actix_web::test::{TestRequest, call_service, init_service, read_body};
+    use actix_web::{App, middleware};
+
+    /// Lines of `output` starting with `prefix`.
+    fn lines<'a>(output: &'a str, prefix: &str) -> Vec<&'a str> {
+        output
+            .lines()
+            .filter(|line| line.starts_with(prefix))
+            .collect()
+    }
+
+    #[test]
+    fn renders_the_text_format() {
+        let metrics = Metrics::new();
+        metrics.observe_request("serve_index", 200, Duration::from_secs_f64(0.03125));
+        metrics.observe_request("serve_index", 200, Duration::from_secs(20));
+        metrics.observe_request("serve_index", 304, Duration::from_secs_f64(0.00390625));
+        metrics.observe_version_check(true);
+        metrics.observe_version_check(false);
+        metrics.observe_sri_download(1024, Duration::from_millis(200));
+        let output = metrics.render(6);
+
+        for (name, kind) in [
+            ("hit_demo_http_requests_total", "counter"),
+            ("hit_demo_http_request_duration_seconds", "histogram"),
+            ("hit_demo_version_checks_total", "counter"),
+            ("hit_demo_version_check_failures_total", "counter"),
+            ("hit_demo_sri_download_bytes_total", "counter"),
+            ("hit_demo_sri_download_duration_seconds", "histogram"),
+            ("hit_demo_versions_cached", "gauge"),
+            ("hit_demo_last_successful_check_age_seconds", "gauge"),
+        ] {
+            let help = format!("# HELP {} ", name);
+            let kind = format!("# TYPE {} {}", name, kind);
+            let help_line = output.lines().position(|line| line.starts_with(&help));
+            let type_line = output.lines().position(|line| line == kind);
+            let first_sample = output
+                .lines()
+                .position(|line| line.starts_with(name) && !line.starts_with('#'));
+            assert!(help_line.is_some() && type_line.is_some(), "{}", name);
+            assert!(
+                help_line < type_line && type_line < first_sample,
+                "{}",
+                name
+            );
+        }
+
+        assert_eq!(
+            lines(&output, "hit_demo_http_requests_total{"),
+            [
+                "hit_demo_http_requests_total{route=\"serve_index\",status=\"200\"} 2",
+                "hit_demo_http_requests_total{route=\"serve_index\",status=\"304\"} 1",
+            ]
+        );
+        assert_eq!(
+            lines(&output, "hit_demo_http_request_duration_seconds"),
+            [
+                "hit_demo_http_request_duration_seconds_bucket{route=\"serve_index\",le=\"0.005\"} 1",
+                "hit_demo_http_request_duration_seconds_bucket{route=\"serve_index\",le=\"0.01\"} 1",
+                "hit_demo_http_request_duration_seconds_bucket{route=\"serve_index\",le=\"0.025\"} 1",
+                "hit_demo_http_request_duration_seconds_bucket{route=\"serve_index\",le=\"0.05\"} 2",
+                "hit_demo_http_request_duration_seconds_bucket{route=\"serve_index\",le=\"0.1\"} 2",
+                "hit_demo_http_request_duration_seconds_bucket{route=\"serve_index\",le=\"0.25\"} 2",
+                "hit_demo_http_request_duration_seconds_bucket{route=\"serve_index\",le=\"0.5\"} 2",
+                "hit_demo_http_request_duration_seconds_bucket{route=\"serve_index\",le=\"1\"} 2",
+                "hit_demo_http_request_duration_seconds_bucket{route=\"serve_index\",le=\"2.5\"} 2",
+                "hit_demo_http_request_duration_seconds_bucket{route=\"serve_index\",le=\"5\"} 2",
+                "hit_demo_http_request_duration_seconds_bucket{route=\"serve_index\",le=\"10\"} 2",
+                "hit_demo_http_request_duration_seconds_bucket{route=\"serve_index\",le=\"+Inf\"} 3",
+                "hit_demo_http_request_duration_seconds_sum{route=\"serve_index\"} 20.03515625",
+                "hit_demo_http_request_duration_seconds_count{route=\"serve_index\"} 3",
+            ]
+        );
+        assert_eq!(
+            lines(
+                &output,
+                "hit_demo_sri_download_duration_seconds_bucket{le=\"0.25\"}"
+            ),
+            ["hit_demo_sri_download_duration_seconds_bucket{le=\"0.25\"} 1"]
+        );
+        assert_eq!(
+            lines(&output, "hit_demo_sri_download_duration_seconds_count"),
+            ["hit_demo_sri_download_duration_seconds_count 1"]
+        );
+        for line in [
+            "hit_demo_version_checks_total 2",
+            "hit_demo_version_check_failures_total 1",
+            "hit_demo_sri_download_bytes_total 1024",
+            "hit_demo_versions_cached 6",
+        ] {
+            assert!(output.lines().any(|l| l == line), "{}", line);
+        }
+    }
+
+    #[test]
+    fn omits_the_check_age_before_a_successful_check() {
+        let metrics = Metrics::new();
+        metrics.observe_version_check(false);
+
+        assert!(!metrics.render(0).contains("last_successful_check"));
+    }
+
+    #[test]
+    fn escapes_label_values() {
+        let metrics = Metrics::new();
+        metrics.observe_request("a\"b\\c\nd", 200, Duration::from_millis(1));
+        let output = metrics.render(0);
+
+        assert!(output.contains("{route=\"a\\\"b\\\\c\\nd\",status=\"200\"} 1\n"));
+        assert!(output.contains("_count{route=\"a\\\"b\\\\c\\nd\"} 1\n"));
+        assert_eq!(
+            output
+                .lines()
+                .filter(|line| line.starts_with("d\""))
+                .count(),
+            0
+        );
+    }
+
+    #[actix_web::test]
+    async fn records_unmatched_routes_as_not_found() {
+        let metrics = Metrics::new();
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(metrics.clone()))
+                .wrap(middleware::from_fn(record_request))
+                .service(web::resource("/known").name("known").to(HttpResponse::Ok)),
+        )
+        .await;
+
+        for uri in ["/known", "/unknown", "/unknown/deeper"] {
+            call_service(&app, TestRequest::get().uri(uri).to_request()).await;
+        }
+
+        assert_eq!(
+            lines(&metrics.render(0), "hit_demo_http_requests_total{"),
+            [
+                "hit_demo_http_requests_total{route=\"known\",status=\"200\"} 1",
+                "hit_demo_http_requests_total{route=\"not_found\",status=\"404\"} 2",
+            ]
+        );
+    }
+
+    #[actix_web::test]
+    async fn requires_the_configured_token() {
+        let config = Config {
+            metrics_token: Some("s3cret".to_string()),
+            ..Config::default()
+        };
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(Metrics::new()))
+                .app_data(web::Data::new(
+                    VersionChecker::with_versions(sample_versions(), &[]).await,
+                ))
+                .app_data(web::Data::new(config))
+                .service(serve_metrics),
+        )
+        .await;
+
+        for authorization in [
+            None,
+            Some("Bearer wrong"),
+            Some("Bearer s3cre"),
+            Some("Bearer s3cret2"),
+            Some("Basic s3cret"),
+            Some("s3cret"),
+        ] {
+            let mut req = TestRequest::get().uri("/metrics");
+            if let Some(authorization) = authorization {
+                req = req.insert_header((AUTHORIZATION, authorization));
+            }
+            let response = call_service(&app, req.to_request()).await;
+            assert_eq!(
+                response.status(),
+                StatusCode::UNAUTHORIZED,
+                "{:?}",
+                authorization
+            );
+            assert_eq!(response.headers().get(WWW_AUTHENTICATE).unwrap(), "Bearer");
+        }
+
+        let req = TestRequest::get()
+            .uri("/metrics")
+            .insert_header((AUTHORIZATION, "Bearer s3cret"))
+            .to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = read_body(response).await;
+        assert!(
+            std::str::from_utf8(&body)
+                .unwrap()
+                .contains("hit_demo_versions_cached 6\n")
+        );
+    }
+
+    #[actix_web::test]
+    async fn is_open_without_a_token() {
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(Metrics::new()))
+                .app_data(web::Data::new(
+                    VersionChecker::with_versions(sample_versions(), &[]).await,
+                ))
+                .app_data(web::Data::new(Config::default()))
+                .service(serve_metrics),
+        )
+        .await;
+
+        let response = call_service(&app, TestRequest::get().uri("/metrics").to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
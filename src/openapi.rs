@@ -0,0 +1,288 @@
+use crate::asset_manager::{AssetManager, EncodedContent};
+use crate::security::request_nonce;
+use crate::template::TemplateContext;
+use crate::utils::{create_encoded_response, create_page_response, get_hit_demo_version};
+use actix_web::{HttpRequest, HttpResponse, Responder, get, web};
+use std::sync::OnceLock;
+use std::time::SystemTime;
+use tracing::error;
+use utoipa::OpenApi;
+
+pub const API_DOCS_TEMPLATE: &str = "api-docs.min.html";
+
+/// Values of the API docs template, also used to validate it at startup.
+pub fn api_docs_context(csp_nonce: String) -> TemplateContext {
+    let mut context = TemplateContext::new();
+    context
+        .insert("demo_version", get_hit_demo_version())
+        .insert("csp_nonce", csp_nonce);
+    context
+}
+
+/// The JSON API, described from the handlers and the types they serialize
+/// so the document cannot drift from the responses.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "highlight-it demo API",
+        description = "Versions of highlight-it available on the demo, with their CDN URLs and Subresource Integrity hashes. Errors are returned as an ApiError object. The endpoints are also served under /api without the version prefix."
+    ),
+    paths(
+        crate::api::serve_latest_version_api,
+        crate::api::serve_all_versions_api,
+        crate::api::serve_version_api,
+        crate::api::serve_resolve_api,
+        crate::api::serve_snippet_api
+    ),
+    tags((name = "versions", description = "highlight-it releases"))
+)]
+struct ApiDoc;
+
+/// The document serialized once with its compressed variants, and the time
+/// it was built.
+fn openapi_document() -> &'static (EncodedContent, SystemTime) {
+    static DOCUMENT: OnceLock<(EncodedContent, SystemTime)> = OnceLock::new();
+
+    DOCUMENT.get_or_init(|| {
+        let json = ApiDoc::openapi().to_pretty_json().unwrap_or_else(|e| {
+            error!(error = %e, "Failed to serialize OpenAPI document");
+            String::from("{}")
+        });
+        (EncodedContent::new(json.as_bytes()), SystemTime::now())
+    })
+}
+
+#[get("/openapi.json")]
+pub async fn serve_openapi(req: HttpRequest) -> impl Responder {
+    let (document, built_at) = openapi_document();
+
+    let builder = HttpResponse::Ok()
+        .content_type("application/json")
+        .append_header(("Cache-Control", "public, max-age=3600"))
+        .take();
+
+    create_encoded_response(&req, builder, document, *built_at)
+}
+
+#[get("/docs")]
+pub async fn serve_api_docs(
+    req: HttpRequest,
+    asset_manager: web::Data<AssetManager>,
+) -> impl Responder {
+    let Some(template) = asset_manager.get_template(API_DOCS_TEMPLATE).await else {
+        return HttpResponse::InternalServerError().body("Template not found");
+    };
+
+    let content = match template
+        .compiled
+        .render(&api_docs_context(request_nonce(&req)))
+    {
+        Ok(content) => content,
+        Err(e) => {
+            error!(template = API_DOCS_TEMPLATE, error = %e, "Template rendering failed");
+            return HttpResponse::InternalServerError().body("Template rendering failed");
+        }
+    };
+
+    let builder = HttpResponse::Ok()
+        .content_type("text/html")
+        .append_header(("Cache-Control", "public, max-age=3600"))
+        .take();
+
+    create_page_response(&req, builder, &asset_manager, content, template.modified).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api;
+    use crate::version_checker::{VersionChecker, sample_versions};
+    use actix_web::App;
+    use actix_web::test::{TestRequest, call_service, init_service, read_body_json};
+    use serde_json::Value;
+
+    /// Resolves a `#/components/schemas/...` reference.
+    fn resolve<'a>(schema: &'a Value, document: &'a Value) -> &'a Value {
+        match schema["$ref"].as_str() {
+            Some(reference) => {
+                let pointer = reference.trim_start_matches('#');
+                resolve(document.pointer(pointer).unwrap(), document)
+            }
+            None => schema,
+        }
+    }
+
+    /// Properties declared by an object schema, including those of `allOf`
+    /// parts.
+    fn declared_properties<'a>(schema: &'a Value, document: &'a Value) -> Vec<&'a str> {
+        let schema = resolve(schema, document);
+        let mut properties: Vec<&str> = schema["properties"]
+            .as_object()
+            .map(|properties| properties.keys().map(String::as_str).collect())
+            .unwrap_or_default();
+
+        for part in schema["allOf"].as_array().into_iter().flatten() {
+            properties.extend(declared_properties(part, document));
+        }
+
+        properties
+    }
+
+    fn has_type(value: &Value, name: &str) -> bool {
+        match name {
+            "null" => value.is_null(),
+            "boolean" => value.is_boolean(),
+            "string" => value.is_string(),
+            "integer" => value.is_u64() || value.is_i64(),
+            "number" => value.is_number(),
+            "array" => value.is_array(),
+            "object" => value.is_object(),
+            _ => false,
+        }
+    }
+
+    /// Checks `value` against a JSON schema of the document, covering the
+    /// keywords utoipa generates for the response types. Objects may not
+    /// carry undeclared properties, so a new field must be documented.
+    fn validate(value: &Value, schema: &Value, document: &Value, at: &str) -> Result<(), String> {
+        validate_schema(value, schema, document, at, true)
+    }
+
+    /// `closed` rejects undeclared properties, which is left to the
+    /// enclosing schema for the parts of an `allOf`.
+    fn validate_schema(
+        value: &Value,
+        schema: &Value,
+        document: &Value,
+        at: &str,
+        closed: bool,
+    ) -> Result<(), String> {
+        let schema = resolve(schema, document);
+
+        for part in schema["allOf"].as_array().into_iter().flatten() {
+            validate_schema(value, part, document, at, false)?;
+        }
+        for keyword in ["oneOf", "anyOf"] {
+            if let Some(parts) = schema[keyword].as_array() {
+                if !parts
+                    .iter()
+                    .any(|part| validate(value, part, document, at).is_ok())
+                {
+                    return Err(format!("{}: matches no {} schema", at, keyword));
+                }
+            }
+        }
+
+        let types: Vec<&str> = match &schema["type"] {
+            Value::String(name) => vec![name.as_str()],
+            Value::Array(names) => names.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        if !types.is_empty() && !types.iter().any(|name| has_type(value, name)) {
+            return Err(format!("{}: {} is not of type {:?}", at, value, types));
+        }
+
+        if let Some(allowed) = schema["enum"].as_array() {
+            if !allowed.contains(value) {
+                return Err(format!("{}: {} is not one of {:?}", at, value, allowed));
+            }
+        }
+
+        if let Value::Object(object) = value {
+            for required in schema["required"].as_array().into_iter().flatten() {
+                let required = required.as_str().unwrap_or_default();
+                if !object.contains_key(required) {
+                    return Err(format!("{}: missing {}", at, required));
+                }
+            }
+
+            let declared = declared_properties(schema, document);
+            for (name, property) in object {
+                if closed && !declared.contains(&name.as_str()) {
+                    return Err(format!("{}: undocumented property {}", at, name));
+                }
+                if let Some(property_schema) = schema["properties"].get(name) {
+                    validate(
+                        property,
+                        property_schema,
+                        document,
+                        &format!("{}.{}", at, name),
+                    )?;
+                }
+            }
+        }
+
+        if let (Value::Array(items), Some(items_schema)) = (value, schema.get("items")) {
+            for (index, item) in items.iter().enumerate() {
+                validate(item, items_schema, document, &format!("{}[{}]", at, index))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn responses_match_the_schema() {
+        let document = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let checker =
+            VersionChecker::with_versions(sample_versions(), &[("latest", "1.2.3")]).await;
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(checker))
+                .configure(api::configure),
+        )
+        .await;
+
+        let cases = [
+            ("/api/v1/latest", "/api/v1/latest", 200),
+            ("/api/v1/versions", "/api/v1/versions", 200),
+            ("/api/v1/versions?limit=2", "/api/v1/versions", 200),
+            ("/api/v1/versions?limit=0", "/api/v1/versions", 400),
+            ("/api/v1/versions/1.2.2", "/api/v1/versions/{version}", 200),
+            ("/api/v1/versions/0.2.1", "/api/v1/versions/{version}", 200),
+            ("/api/v1/versions/x", "/api/v1/versions/{version}", 400),
+            ("/api/v1/versions/9.9.9", "/api/v1/versions/{version}", 404),
+            ("/api/v1/resolve?range=%5E1.1", "/api/v1/resolve", 200),
+            ("/api/v1/resolve?range=latest", "/api/v1/resolve", 200),
+            ("/api/v1/resolve?range=%3E%3E1", "/api/v1/resolve", 400),
+            ("/api/v1/resolve?range=%5E5", "/api/v1/resolve", 404),
+            ("/api/v1/snippet?style=unknown", "/api/v1/snippet", 400),
+            ("/api/v1/snippet?version=9.9.9", "/api/v1/snippet", 404),
+        ];
+
+        for (uri, path, status) in cases {
+            let response = call_service(&app, TestRequest::get().uri(uri).to_request()).await;
+            assert_eq!(response.status().as_u16(), status, "{}", uri);
+
+            let body: Value = read_body_json(response).await;
+            let schema = &document["paths"][path]["get"]["responses"][status.to_string()]["content"]
+                ["application/json"]["schema"];
+            assert!(!schema.is_null(), "{} {} is not documented", path, status);
+
+            if let Err(e) = validate(&body, schema, &document, uri) {
+                panic!("{}", e);
+            }
+        }
+    }
+
+    #[test]
+    fn validator_rejects_mismatches() {
+        let document = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let schema = serde_json::json!({ "$ref": "#/components/schemas/ApiError" });
+
+        let valid = serde_json::json!({ "code": "not_found", "message": "", "request_id": "1" });
+        assert!(validate(&valid, &schema, &document, "").is_ok());
+
+        for invalid in [
+            serde_json::json!({ "code": "not_found", "message": "" }),
+            serde_json::json!({ "code": 404, "message": "", "request_id": "1" }),
+            serde_json::json!({ "code": "", "message": "", "request_id": "1", "extra": true }),
+        ] {
+            assert!(
+                validate(&invalid, &schema, &document, "").is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+}
@@ -0,0 +1,420 @@
+use crate::api;
+use crate::config::{RateLimit, RateLimits};
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::StatusCode;
+use actix_web::http::header::{CACHE_CONTROL, HeaderValue, RETRY_AFTER};
+use actix_web::middleware::Next;
+use actix_web::{Error, HttpRequest, HttpResponse, web};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv6Addr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How often buckets that have refilled completely are dropped
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Paths that are never limited, so probes and scrapers keep working
+const EXEMPT_PATHS: [&str; 3] = ["/healthz", "/readyz", "/metrics"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RouteGroup {
+    Pages,
+    Api,
+    Static,
+    NotFound,
+}
+
+impl RouteGroup {
+    fn from_path(path: &str) -> Option<Self> {
+        if EXEMPT_PATHS.contains(&path) {
+            None
+        } else if path.starts_with("/static/") {
+            Some(RouteGroup::Static)
+        } else if api::is_api_path(path) {
+            Some(RouteGroup::Api)
+        } else {
+            Some(RouteGroup::Pages)
+        }
+    }
+}
+
+fn group_limit(limits: &RateLimits, group: RouteGroup) -> &RateLimit {
+    match group {
+        RouteGroup::Pages => &limits.pages,
+        RouteGroup::Api => &limits.api,
+        RouteGroup::Static => &limits.static_files,
+        RouteGroup::NotFound => &limits.not_found,
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+struct Buckets {
+    buckets: HashMap<(IpAddr, RouteGroup), Bucket>,
+    cleaned: Instant,
+}
+
+/// Token buckets per client and route group. Clones share the same state.
+#[derive(Clone)]
+pub struct RateLimiter {
+    limits: RateLimits,
+    trusted_proxies: Arc<Vec<IpAddr>>,
+    state: Arc<Mutex<Buckets>>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits, trusted_proxies: Vec<IpAddr>) -> Self {
+        Self {
+            limits,
+            trusted_proxies: Arc::new(trusted_proxies),
+            state: Arc::new(Mutex::new(Buckets {
+                buckets: HashMap::new(),
+                cleaned: Instant::now(),
+            })),
+        }
+    }
+
+    /// Takes a token for the client, or returns how long until one is free.
+    fn acquire(&self, client: IpAddr, group: RouteGroup) -> Result<(), Duration> {
+        let limit = group_limit(&self.limits, group);
+        if limit.rate <= 0.0 {
+            return Ok(());
+        }
+
+        let Ok(mut state) = self.state.lock() else {
+            return Ok(());
+        };
+        let now = Instant::now();
+
+        if now.duration_since(state.cleaned) >= CLEANUP_INTERVAL {
+            let limits = &self.limits;
+            state.buckets.retain(|(_, group), bucket| {
+                let limit = group_limit(limits, *group);
+                bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * limit.rate
+                    < limit.burst
+            });
+            state.cleaned = now;
+        }
+
+        let bucket = state
+            .buckets
+            .entry((client_key(client), group))
+            .or_insert(Bucket {
+                tokens: limit.burst,
+                updated: now,
+            });
+
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * limit.rate).min(limit.burst);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / limit.rate))
+        }
+    }
+
+    /// The address of the client, taken from `X-Forwarded-For` only when the
+    /// connection comes from a trusted proxy or a local Unix socket.
+    pub fn client_ip(&self, req: &HttpRequest) -> Option<IpAddr> {
+        let peer = req.peer_addr().map(|address| address.ip());
+
+        if peer.is_some_and(|ip| !self.trusted_proxies.contains(&ip)) {
+            return peer;
+        }
+
+        let forwarded = req
+            .headers()
+            .get_all("X-Forwarded-For")
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|address| address.trim().parse::<IpAddr>().ok())
+            .collect::<Vec<_>>();
+
+        forwarded
+            .iter()
+            .rev()
+            .find(|ip| !self.trusted_proxies.contains(ip))
+            .or(forwarded.first())
+            .copied()
+            .or(peer)
+    }
+
+    /// Takes a token of `group` for the client sending `req`, returning the
+    /// 429 response to send instead if none is left.
+    pub fn check(&self, req: &HttpRequest, group: RouteGroup) -> Option<HttpResponse> {
+        let client = self.client_ip(req)?;
+        let retry_after = self.acquire(client, group).err()?;
+        Some(too_many_requests(req, group, retry_after))
+    }
+}
+
+/// IPv6 clients usually control a whole /64, so they share one bucket.
+fn client_key(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => IpAddr::V4(ip),
+            None => {
+                let segments = ip.segments();
+                IpAddr::V6(Ipv6Addr::new(
+                    segments[0],
+                    segments[1],
+                    segments[2],
+                    segments[3],
+                    0,
+                    0,
+                    0,
+                    0,
+                ))
+            }
+        },
+        ip => ip,
+    }
+}
+
+/// A 429 response, with a JSON body for API requests.
+fn too_many_requests(req: &HttpRequest, group: RouteGroup, retry_after: Duration) -> HttpResponse {
+    let seconds = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+
+    let mut response = match group {
+        RouteGroup::Api => {
+            api::error_response(req, StatusCode::TOO_MANY_REQUESTS, "Too many requests")
+        }
+        _ => HttpResponse::TooManyRequests()
+            .content_type("text/plain")
+            .body("Too many requests\n"),
+    };
+
+    let headers = response.headers_mut();
+    headers.insert(RETRY_AFTER, HeaderValue::from(seconds));
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    response
+}
+
+/// Middleware applying the page, API and static limits by path. The 404
+/// limit is applied where the not found page is rendered.
+pub async fn limit_request(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let limiter = req.app_data::<web::Data<RateLimiter>>().cloned();
+
+    if let (Some(limiter), Some(group)) = (limiter, RouteGroup::from_path(req.path())) {
+        if let Some(response) = limiter.check(req.request(), group) {
+            return Ok(req.into_response(response).map_into_right_body());
+        }
+    }
+
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use actix_web::http::header::CONTENT_TYPE;
+    use actix_web::test::{TestRequest, call_service, init_service, read_body_json};
+    use actix_web::{App, middleware};
+    use serde_json::Value;
+
+    fn limit(rate: f64, burst: f64) -> RateLimit {
+        RateLimit { rate, burst }
+    }
+
+    fn limiter(limits: RateLimits) -> RateLimiter {
+        RateLimiter::new(limits, Vec::new())
+    }
+
+    /// Moves the last refill of every bucket `age` into the past.
+    fn age_buckets(limiter: &RateLimiter, age: Duration) {
+        for bucket in limiter.state.lock().unwrap().buckets.values_mut() {
+            bucket.updated -= age;
+        }
+    }
+
+    fn client_ip(peer: &str, forwarded: Option<&str>) -> Option<IpAddr> {
+        let config = Config::default();
+        let limiter = RateLimiter::new(config.rate_limits, config.trusted_proxies);
+
+        let mut req = TestRequest::default().peer_addr(peer.parse().unwrap());
+        if let Some(forwarded) = forwarded {
+            req = req.insert_header(("X-Forwarded-For", forwarded));
+        }
+        limiter.client_ip(&req.to_http_request())
+    }
+
+    #[test]
+    fn trusts_loopback_proxy_by_default() {
+        let client = "203.0.113.7".parse().ok();
+
+        assert_eq!(client_ip("127.0.0.1:4000", Some("203.0.113.7")), client);
+        assert_eq!(client_ip("[::1]:4000", Some("203.0.113.7")), client);
+        assert_eq!(
+            client_ip("127.0.0.1:4000", Some("198.51.100.1, 203.0.113.7")),
+            client
+        );
+        assert_eq!(client_ip("127.0.0.1:4000", None), "127.0.0.1".parse().ok());
+    }
+
+    #[test]
+    fn ignores_forwarded_header_from_other_peers() {
+        assert_eq!(
+            client_ip("192.0.2.1:4000", Some("203.0.113.7")),
+            "192.0.2.1".parse().ok()
+        );
+    }
+
+    #[test]
+    fn is_disabled_by_default() {
+        let limiter = limiter(Config::default().rate_limits);
+        let client = "203.0.113.7".parse().unwrap();
+
+        for group in [
+            RouteGroup::Pages,
+            RouteGroup::Api,
+            RouteGroup::Static,
+            RouteGroup::NotFound,
+        ] {
+            for _ in 0..1000 {
+                assert_eq!(limiter.acquire(client, group), Ok(()));
+            }
+        }
+    }
+
+    #[test]
+    fn allows_a_burst_then_refills() {
+        let limiter = limiter(RateLimits {
+            pages: limit(2.0, 3.0),
+            ..RateLimits::default()
+        });
+        let client = "203.0.113.7".parse().unwrap();
+
+        for _ in 0..3 {
+            assert_eq!(limiter.acquire(client, RouteGroup::Pages), Ok(()));
+        }
+        let retry_after = limiter.acquire(client, RouteGroup::Pages).unwrap_err();
+        assert!(retry_after > Duration::from_millis(400));
+        assert!(retry_after <= Duration::from_millis(500));
+
+        // Other groups and clients have their own buckets
+        assert_eq!(limiter.acquire(client, RouteGroup::Api), Ok(()));
+        let other = "203.0.113.8".parse().unwrap();
+        assert_eq!(limiter.acquire(other, RouteGroup::Pages), Ok(()));
+
+        age_buckets(&limiter, Duration::from_secs(1));
+        for _ in 0..2 {
+            assert_eq!(limiter.acquire(client, RouteGroup::Pages), Ok(()));
+        }
+        assert!(limiter.acquire(client, RouteGroup::Pages).is_err());
+
+        // Refilling never goes beyond the burst
+        age_buckets(&limiter, Duration::from_secs(60));
+        for _ in 0..3 {
+            assert_eq!(limiter.acquire(client, RouteGroup::Pages), Ok(()));
+        }
+        assert!(limiter.acquire(client, RouteGroup::Pages).is_err());
+    }
+
+    #[test]
+    fn groups_ipv6_clients_by_64() {
+        let limiter = limiter(RateLimits {
+            api: limit(1.0, 1.0),
+            ..RateLimits::default()
+        });
+        let ip = |ip: &str| ip.parse::<IpAddr>().unwrap();
+
+        assert_eq!(
+            limiter.acquire(ip("2001:db8:1:2::1"), RouteGroup::Api),
+            Ok(())
+        );
+        assert!(
+            limiter
+                .acquire(ip("2001:db8:1:2:abcd::9"), RouteGroup::Api)
+                .is_err()
+        );
+        assert_eq!(
+            limiter.acquire(ip("2001:db8:1:3::1"), RouteGroup::Api),
+            Ok(())
+        );
+
+        assert_eq!(
+            limiter.acquire(ip("::ffff:192.0.2.1"), RouteGroup::Api),
+            Ok(())
+        );
+        assert!(limiter.acquire(ip("192.0.2.1"), RouteGroup::Api).is_err());
+        assert_eq!(limiter.acquire(ip("192.0.2.2"), RouteGroup::Api), Ok(()));
+    }
+
+    #[test]
+    fn groups_routes_by_path() {
+        for path in EXEMPT_PATHS {
+            assert_eq!(RouteGroup::from_path(path), None, "{}", path);
+        }
+        for (path, group) in [
+            ("/", RouteGroup::Pages),
+            ("/1.2.3", RouteGroup::Pages),
+            ("/badge/latest.svg", RouteGroup::Pages),
+            ("/feed.atom", RouteGroup::Pages),
+            ("/healthz/x", RouteGroup::Pages),
+            ("/static/script.min.js", RouteGroup::Static),
+            ("/api", RouteGroup::Api),
+            ("/api/v1/latest", RouteGroup::Api),
+        ] {
+            assert_eq!(RouteGroup::from_path(path), Some(group), "{}", path);
+        }
+    }
+
+    #[actix_web::test]
+    async fn answers_with_429() {
+        let limiter = limiter(RateLimits {
+            pages: limit(0.5, 1.0),
+            api: limit(0.5, 1.0),
+            ..RateLimits::default()
+        });
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(limiter))
+                .wrap(middleware::from_fn(limit_request))
+                .default_service(web::to(HttpResponse::Ok)),
+        )
+        .await;
+        let get = |path: &str| {
+            TestRequest::get()
+                .uri(path)
+                .peer_addr("203.0.113.7:4000".parse().unwrap())
+                .to_request()
+        };
+
+        for path in ["/", "/api/v1/latest"] {
+            assert_eq!(call_service(&app, get(path)).await.status(), StatusCode::OK);
+        }
+        for _ in 0..3 {
+            assert_eq!(
+                call_service(&app, get("/healthz")).await.status(),
+                StatusCode::OK
+            );
+        }
+
+        let response = call_service(&app, get("/")).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers().get(RETRY_AFTER).unwrap(), "2");
+        assert_eq!(response.headers().get(CACHE_CONTROL).unwrap(), "no-store");
+        assert_eq!(response.headers().get(CONTENT_TYPE).unwrap(), "text/plain");
+
+        let response = call_service(&app, get("/api/v1/latest")).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers().get(RETRY_AFTER).unwrap(), "2");
+        assert_eq!(response.headers().get(CACHE_CONTROL).unwrap(), "no-store");
+        let body: Value = read_body_json(response).await;
+        assert_eq!(body["code"], "rate_limited");
+        assert_eq!(body["message"], "Too many requests");
+    }
+}
@@ -0,0 +1,610 @@
+use crate::config::{SecurityHeaders, SecurityHeadersOverride};
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{
+    CONTENT_SECURITY_POLICY, CONTENT_SECURITY_POLICY_REPORT_ONLY, HeaderName, HeaderValue,
+};
+use actix_web::middleware::Next;
+use actix_web::{Error, HttpMessage, HttpRequest, web};
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::error;
+
+/// Random bytes in each nonce, 128 bits as recommended by the CSP spec
+const NONCE_LENGTH: usize = 16;
+
+/// How long one CSP nonce is used. Pages are cached and revalidated, so the
+/// pages of an epoch share its nonce, and the next nonce changes their ETag.
+const NONCE_EPOCH: Duration = Duration::from_secs(3600);
+
+/// The nonce allowed by this request's Content-Security-Policy. Templates
+/// put it on every script and stylesheet they reference.
+#[derive(Clone)]
+struct CspNonce {
+    value: String,
+    issued: SystemTime,
+}
+
+fn generate_nonce() -> String {
+    let mut bytes = [0u8; NONCE_LENGTH];
+
+    match getrandom::fill(&mut bytes) {
+        Ok(()) => BASE64.encode(bytes),
+        Err(e) => {
+            error!(error = %e, "Failed to generate CSP nonce");
+            String::new()
+        }
+    }
+}
+
+/// The nonce of the current request, empty if the policy of its path does
+/// not use one. Only requests asking for it take the epoch's nonce.
+pub fn request_nonce(req: &HttpRequest) -> String {
+    if let Some(nonce) = req.extensions().get::<CspNonce>() {
+        return nonce.value.clone();
+    }
+
+    let Some(policy) = req.app_data::<web::Data<SecurityPolicy>>() else {
+        return String::new();
+    };
+    if !policy.headers_for(req.path()).uses_nonce() {
+        return String::new();
+    }
+
+    let nonce = policy.current_nonce();
+    req.extensions_mut().insert(nonce.clone());
+    nonce.value
+}
+
+/// When the nonce of the current request was first sent. Pages carrying it
+/// are never older than that.
+pub fn request_nonce_issued(req: &HttpRequest) -> SystemTime {
+    req.extensions()
+        .get::<CspNonce>()
+        .map_or(UNIX_EPOCH, |nonce| nonce.issued)
+}
+
+/// Built-in policy. The placeholder becomes `'nonce-...'`, or nothing for
+/// responses rendered without a nonce.
+const DEFAULT_CONTENT_SECURITY_POLICY: &str = "default-src 'self'; \
+     script-src 'self' {nonce} https://cdn.jsdelivr.net; \
+     style-src 'self' {nonce} https://cdn.jsdelivr.net; \
+     connect-src 'self'; font-src 'self' data:; img-src 'self' data:; \
+     frame-ancestors 'none'";
+
+const NONCE_PLACEHOLDER: &str = "{nonce}";
+
+/// Built-in headers sent with every response unless configured otherwise
+const DEFAULT_HEADERS: [(&str, &str); 8] = [
+    (
+        "Strict-Transport-Security",
+        "max-age=31536000; includeSubDomains",
+    ),
+    ("Referrer-Policy", "strict-origin-when-cross-origin"),
+    ("X-Content-Type-Options", "nosniff"),
+    ("X-Frame-Options", "DENY"),
+    ("Cross-Origin-Embedder-Policy", "require-corp"),
+    ("Cross-Origin-Opener-Policy", "same-origin"),
+    ("Cross-Origin-Resource-Policy", "same-origin"),
+    (
+        "Permissions-Policy",
+        "camera=(), microphone=(), geolocation=()",
+    ),
+];
+
+/// Headers configured as text, keyed by lowercase name. An empty value
+/// removes the header.
+#[derive(Clone)]
+struct HeaderConfig {
+    headers: BTreeMap<String, String>,
+    content_security_policy: String,
+    report_only: bool,
+}
+
+impl HeaderConfig {
+    fn apply(
+        &mut self,
+        headers: &BTreeMap<String, String>,
+        content_security_policy: &Option<String>,
+        report_only: Option<bool>,
+    ) {
+        for (name, value) in headers {
+            self.headers
+                .insert(name.to_ascii_lowercase(), value.clone());
+        }
+        if let Some(policy) = content_security_policy {
+            self.content_security_policy = policy.clone();
+        }
+        if let Some(report_only) = report_only {
+            self.report_only = report_only;
+        }
+    }
+}
+
+/// The headers sent for one path prefix, validated at startup.
+struct HeaderSet {
+    headers: Vec<(HeaderName, HeaderValue)>,
+    content_security_policy: Option<String>,
+    report_only: bool,
+}
+
+impl HeaderSet {
+    fn build(config: HeaderConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut headers = Vec::new();
+
+        for (name, value) in config.headers {
+            if value.is_empty() {
+                continue;
+            }
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|e| format!("Invalid header name {}: {}", name, e))?;
+            let value = HeaderValue::from_str(&value)
+                .map_err(|e| format!("Invalid value for header {}: {}", name, e))?;
+            headers.push((name, value));
+        }
+
+        let content_security_policy = if config.content_security_policy.is_empty() {
+            None
+        } else {
+            HeaderValue::from_str(
+                &config
+                    .content_security_policy
+                    .replace(NONCE_PLACEHOLDER, ""),
+            )
+            .map_err(|e| format!("Invalid Content-Security-Policy: {}", e))?;
+            Some(config.content_security_policy)
+        };
+
+        Ok(Self {
+            headers,
+            content_security_policy,
+            report_only: config.report_only,
+        })
+    }
+
+    fn uses_nonce(&self) -> bool {
+        self.content_security_policy
+            .as_ref()
+            .is_some_and(|policy| policy.contains(NONCE_PLACEHOLDER))
+    }
+
+    fn policy_header(&self, nonce: &str) -> Option<(HeaderName, HeaderValue)> {
+        let policy = self.content_security_policy.as_ref()?;
+        let nonce_source = if nonce.is_empty() {
+            String::new()
+        } else {
+            format!("'nonce-{}'", nonce)
+        };
+
+        let name = if self.report_only {
+            CONTENT_SECURITY_POLICY_REPORT_ONLY
+        } else {
+            CONTENT_SECURITY_POLICY
+        };
+
+        HeaderValue::from_str(&policy.replace(NONCE_PLACEHOLDER, &nonce_source))
+            .ok()
+            .map(|value| (name, value))
+    }
+}
+
+/// Security headers for every path, built from the configured defaults and
+/// per-prefix overrides.
+pub struct SecurityPolicy {
+    default: HeaderSet,
+    /// Longest prefix first, each with the overrides of shorter prefixes
+    /// that also match it already merged in
+    overrides: Vec<(String, HeaderSet)>,
+    /// Nonce of the current epoch, if one was generated
+    nonce: Mutex<Option<CspNonce>>,
+}
+
+impl SecurityPolicy {
+    pub fn new(config: &SecurityHeaders) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut base = HeaderConfig {
+            headers: DEFAULT_HEADERS
+                .iter()
+                .map(|(name, value)| (name.to_ascii_lowercase(), value.to_string()))
+                .collect(),
+            content_security_policy: DEFAULT_CONTENT_SECURITY_POLICY.to_string(),
+            report_only: false,
+        };
+        base.apply(
+            &config.headers,
+            &config.content_security_policy,
+            Some(config.csp_report_only),
+        );
+
+        let mut prefixes: Vec<&SecurityHeadersOverride> = config.overrides.iter().collect();
+        prefixes.sort_by_key(|entry| entry.path_prefix.len());
+
+        let mut overrides = Vec::new();
+        for entry in &prefixes {
+            let mut merged = base.clone();
+            for parent in prefixes
+                .iter()
+                .filter(|parent| entry.path_prefix.starts_with(&parent.path_prefix))
+            {
+                merged.apply(
+                    &parent.headers,
+                    &parent.content_security_policy,
+                    parent.csp_report_only,
+                );
+            }
+            overrides.push((entry.path_prefix.clone(), HeaderSet::build(merged)?));
+        }
+        overrides.reverse();
+
+        Ok(Self {
+            default: HeaderSet::build(base)?,
+            overrides,
+            nonce: Mutex::new(None),
+        })
+    }
+
+    /// The nonce of the current epoch, generating the next one once it is
+    /// over. A failed generation is retried on the next request.
+    fn current_nonce(&self) -> CspNonce {
+        let mut current = self.nonce.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Some(nonce) = current.as_ref() {
+            if nonce.issued.elapsed().is_ok_and(|age| age < NONCE_EPOCH) {
+                return nonce.clone();
+            }
+        }
+
+        let nonce = CspNonce {
+            value: generate_nonce(),
+            issued: SystemTime::now(),
+        };
+        *current = (!nonce.value.is_empty()).then(|| nonce.clone());
+
+        nonce
+    }
+
+    fn headers_for(&self, path: &str) -> &HeaderSet {
+        self.overrides
+            .iter()
+            .find(|(prefix, _)| path.starts_with(prefix.as_str()))
+            .map_or(&self.default, |(_, headers)| headers)
+    }
+}
+
+/// Middleware adding the security headers configured for the request's path,
+/// with the nonce the handler rendered, if any. Headers already set by a
+/// handler are kept.
+pub async fn apply_security_headers(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let policy = req.app_data::<web::Data<SecurityPolicy>>().cloned();
+
+    let mut response = next.call(req).await?;
+
+    if let Some(policy) = policy {
+        let nonce = response
+            .request()
+            .extensions()
+            .get::<CspNonce>()
+            .map(|nonce| nonce.value.clone())
+            .unwrap_or_default();
+        let headers = policy.headers_for(response.request().path());
+        let policy_header = headers.policy_header(&nonce);
+        let response_headers = response.headers_mut();
+
+        for (name, value) in headers.headers.iter().cloned().chain(policy_header) {
+            if !response_headers.contains_key(&name) {
+                response_headers.insert(name, value);
+            }
+        }
+    }
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asset_manager::AssetManager;
+    use crate::utils::create_page_response;
+    use actix_web::http::StatusCode;
+    use actix_web::http::header::{
+        ETAG, HeaderMap, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED,
+    };
+    use actix_web::test::{TestRequest, call_service, init_service, read_body};
+    use actix_web::{App, HttpResponse, middleware};
+
+    fn override_for(
+        path_prefix: &str,
+        headers: &[(&str, &str)],
+        content_security_policy: Option<&str>,
+        csp_report_only: Option<bool>,
+    ) -> SecurityHeadersOverride {
+        SecurityHeadersOverride {
+            path_prefix: path_prefix.to_string(),
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            content_security_policy: content_security_policy.map(str::to_string),
+            csp_report_only,
+        }
+    }
+
+    fn policy(config: &SecurityHeaders) -> web::Data<SecurityPolicy> {
+        web::Data::new(SecurityPolicy::new(config).unwrap())
+    }
+
+    fn age_nonce(policy: &SecurityPolicy, age: Duration) {
+        if let Some(nonce) = policy.nonce.lock().unwrap().as_mut() {
+            nonce.issued -= age;
+        }
+    }
+
+    async fn page(req: HttpRequest, asset_manager: web::Data<AssetManager>) -> HttpResponse {
+        let content = format!("<script nonce=\"{}\"></script>", request_nonce(&req));
+        let builder = HttpResponse::Ok()
+            .content_type("text/html")
+            .append_header(("Cache-Control", "public, max-age=60"))
+            .take();
+
+        create_page_response(&req, builder, &asset_manager, content, UNIX_EPOCH).await
+    }
+
+    #[test]
+    fn reuses_the_nonce_for_an_epoch() {
+        let policy = policy(&SecurityHeaders::default());
+
+        let first = policy.current_nonce();
+        assert_eq!(first.value.len(), 24);
+        assert_eq!(policy.current_nonce().value, first.value);
+
+        age_nonce(&policy, NONCE_EPOCH);
+        let next = policy.current_nonce();
+        assert_ne!(next.value, first.value);
+        assert!(next.issued > first.issued);
+    }
+
+    #[actix_web::test]
+    async fn revalidates_pages_until_the_nonce_changes() {
+        let policy = policy(&SecurityHeaders::default());
+        let asset_manager = AssetManager::new(&[], &[]).await.unwrap();
+        let app = init_service(
+            App::new()
+                .app_data(policy.clone())
+                .app_data(web::Data::new(asset_manager))
+                .wrap(middleware::from_fn(apply_security_headers))
+                .route("/", web::get().to(page)),
+        )
+        .await;
+
+        let fetch = |headers: Vec<(HeaderName, String)>| {
+            let mut req = TestRequest::get().uri("/");
+            for header in headers {
+                req = req.insert_header(header);
+            }
+            call_service(&app, req.to_request())
+        };
+
+        policy.current_nonce();
+        age_nonce(&policy, NONCE_EPOCH / 2);
+
+        let response = fetch(vec![]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = header(response.headers(), ETAG);
+        let last_modified = header(response.headers(), LAST_MODIFIED);
+        let policy_header = header(response.headers(), CONTENT_SECURITY_POLICY);
+        let body = read_body(response).await;
+        let nonce = request_nonce_from(&body);
+        assert!(policy_header.contains(&format!("'nonce-{}'", nonce)));
+
+        let response = fetch(vec![(IF_NONE_MATCH, etag.clone())]).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(
+            header(response.headers(), CONTENT_SECURITY_POLICY),
+            policy_header
+        );
+        let response = fetch(vec![(IF_MODIFIED_SINCE, last_modified.clone())]).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        age_nonce(&policy, NONCE_EPOCH / 2);
+
+        let response = fetch(vec![(IF_NONE_MATCH, etag.clone())]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_ne!(header(response.headers(), ETAG), etag);
+        let next_policy_header = header(response.headers(), CONTENT_SECURITY_POLICY);
+        let next_nonce = request_nonce_from(&read_body(response).await);
+        assert_ne!(next_nonce, nonce);
+        assert!(next_policy_header.contains(&format!("'nonce-{}'", next_nonce)));
+
+        let response = fetch(vec![(IF_MODIFIED_SINCE, last_modified)]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    fn header(headers: &HeaderMap, name: HeaderName) -> String {
+        headers.get(name).unwrap().to_str().unwrap().to_string()
+    }
+
+    fn request_nonce_from(body: &[u8]) -> String {
+        let body = std::str::from_utf8(body).unwrap();
+        body.split('"').nth(1).unwrap().to_string()
+    }
+
+    #[actix_web::test]
+    async fn merges_overrides_by_prefix() {
+        let config = SecurityHeaders {
+            headers: [("Referrer-Policy".to_string(), "same-origin".to_string())].into(),
+            overrides: vec![
+                override_for(
+                    "/embed/v2",
+                    &[("referrer-policy", "no-referrer")],
+                    None,
+                    None,
+                ),
+                override_for(
+                    "/embed",
+                    &[("X-Frame-Options", ""), ("X-Embed", "yes")],
+                    Some("frame-ancestors https://docs.example.com"),
+                    None,
+                ),
+                override_for("/report", &[], None, Some(true)),
+            ],
+            ..SecurityHeaders::default()
+        };
+        let app = init_service(
+            App::new()
+                .app_data(policy(&config))
+                .wrap(middleware::from_fn(apply_security_headers))
+                .default_service(web::to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let headers = |path: &'static str| {
+            let app = &app;
+            async move {
+                call_service(app, TestRequest::get().uri(path).to_request())
+                    .await
+                    .headers()
+                    .clone()
+            }
+        };
+        let value = |headers: &HeaderMap, name: &str| {
+            headers
+                .get(name)
+                .map(|value| value.to_str().unwrap().to_string())
+        };
+
+        let root = headers("/").await;
+        assert_eq!(
+            value(&root, "referrer-policy").as_deref(),
+            Some("same-origin")
+        );
+        assert_eq!(value(&root, "x-frame-options").as_deref(), Some("DENY"));
+        assert!(
+            value(&root, "content-security-policy")
+                .unwrap()
+                .starts_with("default-src")
+        );
+        assert_eq!(value(&root, "x-embed"), None);
+
+        let embed = headers("/embed/page").await;
+        assert_eq!(
+            value(&embed, "referrer-policy").as_deref(),
+            Some("same-origin")
+        );
+        assert_eq!(value(&embed, "x-frame-options"), None);
+        assert_eq!(value(&embed, "x-embed").as_deref(), Some("yes"));
+        assert_eq!(
+            value(&embed, "content-security-policy").as_deref(),
+            Some("frame-ancestors https://docs.example.com")
+        );
+
+        let nested = headers("/embed/v2/page").await;
+        assert_eq!(
+            value(&nested, "referrer-policy").as_deref(),
+            Some("no-referrer")
+        );
+        assert_eq!(value(&nested, "x-frame-options"), None);
+        assert_eq!(value(&nested, "x-embed").as_deref(), Some("yes"));
+        assert_eq!(
+            value(&nested, "content-security-policy").as_deref(),
+            Some("frame-ancestors https://docs.example.com")
+        );
+        assert_eq!(
+            value(&nested, "strict-transport-security").as_deref(),
+            Some("max-age=31536000; includeSubDomains")
+        );
+
+        let report = headers("/report").await;
+        assert_eq!(value(&report, "content-security-policy"), None);
+        assert!(
+            value(&report, "content-security-policy-report-only")
+                .unwrap()
+                .starts_with("default-src")
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_headers() {
+        let invalid = |headers: &[(&str, &str)], policy: Option<&str>| SecurityHeaders {
+            overrides: vec![override_for("/x", headers, policy, None)],
+            ..SecurityHeaders::default()
+        };
+
+        assert!(SecurityPolicy::new(&invalid(&[("Bad Name", "x")], None)).is_err());
+        assert!(SecurityPolicy::new(&invalid(&[("X-Test", "a\nb")], None)).is_err());
+        assert!(SecurityPolicy::new(&invalid(&[], Some("default-src\n'self'"))).is_err());
+        assert!(SecurityPolicy::new(&invalid(&[("X-Test", "ok")], Some("{nonce}"))).is_ok());
+    }
+
+    #[actix_web::test]
+    async fn keeps_headers_set_by_the_handler() {
+        let app = init_service(
+            App::new()
+                .app_data(policy(&SecurityHeaders::default()))
+                .wrap(middleware::from_fn(apply_security_headers))
+                .default_service(web::to(|| async {
+                    HttpResponse::Ok()
+                        .insert_header(("X-Frame-Options", "SAMEORIGIN"))
+                        .insert_header((CONTENT_SECURITY_POLICY, "sandbox"))
+                        .finish()
+                })),
+        )
+        .await;
+
+        let response = call_service(&app, TestRequest::get().uri("/").to_request()).await;
+        let headers = response.headers();
+        assert_eq!(headers.get("x-frame-options").unwrap(), "SAMEORIGIN");
+        assert_eq!(headers.get(CONTENT_SECURITY_POLICY).unwrap(), "sandbox");
+        assert_eq!(headers.get("x-content-type-options").unwrap(), "nosniff");
+    }
+
+    #[actix_web::test]
+    async fn generates_nonces_only_when_used() {
+        let config = SecurityHeaders {
+            overrides: vec![override_for(
+                "/plain",
+                &[],
+                Some("default-src 'self'"),
+                None,
+            )],
+            ..SecurityHeaders::default()
+        };
+        let policy = policy(&config);
+        let app = init_service(
+            App::new()
+                .app_data(policy.clone())
+                .wrap(middleware::from_fn(apply_security_headers))
+                .route("/data.json", web::get().to(HttpResponse::Ok))
+                .route(
+                    "/plain/page",
+                    web::get().to(|req: HttpRequest| async move {
+                        HttpResponse::Ok().body(request_nonce(&req))
+                    }),
+                ),
+        )
+        .await;
+
+        // A response without a rendered nonce gets the policy without one
+        let response = call_service(&app, TestRequest::get().uri("/data.json").to_request()).await;
+        let policy_header = header(response.headers(), CONTENT_SECURITY_POLICY);
+        assert!(!policy_header.contains("'nonce-"), "{}", policy_header);
+        assert!(
+            policy_header.contains("script-src 'self'"),
+            "{}",
+            policy_header
+        );
+
+        // A policy without the placeholder never needs one
+        let response = call_service(&app, TestRequest::get().uri("/plain/page").to_request()).await;
+        assert_eq!(
+            header(response.headers(), CONTENT_SECURITY_POLICY),
+            "default-src 'self'"
+        );
+        assert!(read_body(response).await.is_empty());
+
+        assert!(policy.nonce.lock().unwrap().is_none());
+    }
+}
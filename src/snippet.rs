@@ -0,0 +1,312 @@
+use crate::utils::get_cdn_url;
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+/// Stylesheets shipped in highlight-it's `dist/styles`, besides the default
+/// one bundled with the script
+pub const STYLES: [&str; 10] = [
+    "default",
+    "github",
+    "vs2015",
+    "intellij-light",
+    "atom-one",
+    "stackoverflow",
+    "monokai",
+    "nord",
+    "a11y",
+    "night-owl",
+];
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SnippetFormat {
+    /// `<link>` and `<script>` tags
+    #[default]
+    Html,
+    /// A React fragment
+    Jsx,
+    /// The HTML in a fenced code block
+    Markdown,
+    /// An import map pinning the ES module build
+    Importmap,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SnippetTheme {
+    #[default]
+    Auto,
+    Light,
+    Dark,
+}
+
+impl SnippetTheme {
+    fn as_str(self) -> &'static str {
+        match self {
+            SnippetTheme::Auto => "auto",
+            SnippetTheme::Light => "light",
+            SnippetTheme::Dark => "dark",
+        }
+    }
+}
+
+impl SnippetFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            SnippetFormat::Markdown => "text/markdown; charset=utf-8",
+            _ => "text/plain; charset=utf-8",
+        }
+    }
+}
+
+pub fn is_known_style(style: &str) -> bool {
+    STYLES.contains(&style)
+}
+
+/// A file on the CDN and the SRI hash it must match
+pub struct Pinned {
+    pub url: String,
+    pub sri_hash: String,
+}
+
+/// The files a snippet loads
+pub struct Embed {
+    /// The minified script, or its ES module build for `Importmap`
+    pub script: Pinned,
+    pub stylesheet: Option<Pinned>,
+}
+
+/// URL of the script a snippet of `format` loads
+pub fn script_url(format: SnippetFormat, version: &str) -> String {
+    match format {
+        SnippetFormat::Importmap => {
+            format!("https://cdn.jsdelivr.net/npm/highlight-it@{}/+esm", version)
+        }
+        _ => get_cdn_url(version),
+    }
+}
+
+/// URL of `style`, `None` for the default one bundled with the script
+pub fn style_url(version: &str, style: &str) -> Option<String> {
+    (style != "default").then(|| {
+        format!(
+            "https://cdn.jsdelivr.net/npm/highlight-it@{}/dist/styles/{}.min.css",
+            version, style
+        )
+    })
+}
+
+fn stylesheet_tag(stylesheet: &Option<Pinned>) -> String {
+    stylesheet.as_ref().map_or(String::new(), |stylesheet| {
+        format!(
+            "<link rel=\"stylesheet\" href=\"{}\" integrity=\"{}\" crossorigin=\"anonymous\">\n",
+            stylesheet.url, stylesheet.sri_hash
+        )
+    })
+}
+
+fn html(embed: &Embed, theme: SnippetTheme) -> String {
+    format!(
+        "{}<script src=\"{}\" integrity=\"{}\" crossorigin=\"anonymous\"></script>\n\
+         <script>\n\
+         \x20   window.addEventListener('load', () => {{\n\
+         \x20       HighlightIt.init({{ theme: '{}' }});\n\
+         \x20   }});\n\
+         </script>\n",
+        stylesheet_tag(&embed.stylesheet),
+        embed.script.url,
+        embed.script.sri_hash,
+        theme.as_str()
+    )
+}
+
+/// A component adding the tags itself, since React does not run the
+/// `<script>` elements it renders
+fn jsx(embed: &Embed, theme: SnippetTheme) -> String {
+    let mut snippet = String::from(
+        "import { useEffect } from 'react';\n\
+         \n\
+         export function HighlightItLoader() {\n\
+         \x20 useEffect(() => {\n",
+    );
+
+    if let Some(stylesheet) = &embed.stylesheet {
+        snippet.push_str(&format!(
+            "    const stylesheet = document.createElement('link');\n\
+             \x20   stylesheet.rel = 'stylesheet';\n\
+             \x20   stylesheet.href = '{}';\n\
+             \x20   stylesheet.integrity = '{}';\n\
+             \x20   stylesheet.crossOrigin = 'anonymous';\n\
+             \x20   document.head.appendChild(stylesheet);\n\
+             \n",
+            stylesheet.url, stylesheet.sri_hash
+        ));
+    }
+
+    snippet.push_str(&format!(
+        "    const script = document.createElement('script');\n\
+         \x20   script.src = '{}';\n\
+         \x20   script.integrity = '{}';\n\
+         \x20   script.crossOrigin = 'anonymous';\n\
+         \x20   script.onload = () => window.HighlightIt.init({{ theme: '{}' }});\n\
+         \x20   document.head.appendChild(script);\n\
+         \n\
+         \x20   return () => {{\n\
+         \x20     script.remove();\n",
+        embed.script.url,
+        embed.script.sri_hash,
+        theme.as_str()
+    ));
+    if embed.stylesheet.is_some() {
+        snippet.push_str("      stylesheet.remove();\n");
+    }
+    snippet.push_str(
+        "    };\n\
+         \x20 }, []);\n\
+         \n\
+         \x20 return null;\n\
+         }\n",
+    );
+
+    snippet
+}
+
+fn importmap(embed: &Embed, theme: SnippetTheme) -> String {
+    format!(
+        "{}<script type=\"importmap\">\n\
+         {{\n\
+         \x20 \"imports\": {{\n\
+         \x20   \"highlight-it\": \"{url}\"\n\
+         \x20 }},\n\
+         \x20 \"integrity\": {{\n\
+         \x20   \"{url}\": \"{sri_hash}\"\n\
+         \x20 }}\n\
+         }}\n\
+         </script>\n\
+         <script type=\"module\">\n\
+         \x20 import HighlightIt from 'highlight-it';\n\
+         \x20 HighlightIt.init({{ theme: '{theme}' }});\n\
+         </script>\n",
+        stylesheet_tag(&embed.stylesheet),
+        url = embed.script.url,
+        sri_hash = embed.script.sri_hash,
+        theme = theme.as_str()
+    )
+}
+
+/// Code that loads the files of `embed`, each pinned by its SRI hash.
+pub fn render(format: SnippetFormat, embed: &Embed, theme: SnippetTheme) -> String {
+    match format {
+        SnippetFormat::Html => html(embed, theme),
+        SnippetFormat::Jsx => jsx(embed, theme),
+        SnippetFormat::Markdown => format!("```html\n{}```\n", html(embed, theme)),
+        SnippetFormat::Importmap => importmap(embed, theme),
+    }
+}
+
+/// Markdown describing a version, with its CDN URL, SRI hash and the HTML
+/// snippet loading it.
+pub fn markdown_page(version: &str, sri_hash: &str, deprecated: Option<&str>) -> String {
+    let mut page = format!("# highlight-it {}\n\n", version);
+
+    if let Some(message) = deprecated {
+        page.push_str(&format!("> **Deprecated:** {}\n\n", message));
+    }
+
+    page.push_str(&format!(
+        "- CDN URL: <{}>\n- SRI hash: `{}`\n\n{}",
+        get_cdn_url(version),
+        sri_hash,
+        render(
+            SnippetFormat::Markdown,
+            &Embed {
+                script: Pinned {
+                    url: get_cdn_url(version),
+                    sri_hash: sri_hash.to_string(),
+                },
+                stylesheet: None,
+            },
+            SnippetTheme::Auto
+        )
+    ));
+
+    page
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn embed(stylesheet: bool) -> Embed {
+        Embed {
+            script: Pinned {
+                url: "https://cdn.example/script.js".to_string(),
+                sri_hash: "sha512-script".to_string(),
+            },
+            stylesheet: stylesheet.then(|| Pinned {
+                url: "https://cdn.example/style.css".to_string(),
+                sri_hash: "sha512-style".to_string(),
+            }),
+        }
+    }
+
+    #[test]
+    fn pins_the_stylesheet() {
+        for format in [
+            SnippetFormat::Html,
+            SnippetFormat::Jsx,
+            SnippetFormat::Markdown,
+            SnippetFormat::Importmap,
+        ] {
+            let snippet = render(format, &embed(true), SnippetTheme::Dark);
+            for expected in ["sha512-script", "style.css", "sha512-style", "'dark'"] {
+                assert!(snippet.contains(expected), "{:?}: {}", format, snippet);
+            }
+
+            let snippet = render(format, &embed(false), SnippetTheme::Dark);
+            assert!(!snippet.contains("stylesheet"), "{:?}: {}", format, snippet);
+        }
+    }
+
+    #[test]
+    fn injects_the_script_from_react() {
+        let snippet = render(SnippetFormat::Jsx, &embed(true), SnippetTheme::Auto);
+
+        assert!(snippet.contains("useEffect(() => {"));
+        assert!(snippet.contains("document.createElement('script')"));
+        assert!(snippet.contains("script.integrity = 'sha512-script';"));
+        assert!(snippet.contains("script.crossOrigin = 'anonymous';"));
+        assert!(snippet.contains("stylesheet.integrity = 'sha512-style';"));
+        assert!(snippet.contains("stylesheet.remove();"));
+        assert!(!snippet.contains("<script"));
+    }
+
+    #[test]
+    fn maps_the_module_build() {
+        assert_eq!(
+            script_url(SnippetFormat::Importmap, "1.2.3"),
+            "https://cdn.jsdelivr.net/npm/highlight-it@1.2.3/+esm"
+        );
+        assert_eq!(
+            script_url(SnippetFormat::Html, "1.2.3"),
+            get_cdn_url("1.2.3")
+        );
+
+        let snippet = render(SnippetFormat::Importmap, &embed(false), SnippetTheme::Auto);
+        let map = snippet
+            .split_once("<script type=\"importmap\">")
+            .and_then(|(_, rest)| rest.split_once("</script>"))
+            .map(|(map, _)| map)
+            .unwrap();
+        let map: serde_json::Value = serde_json::from_str(map).unwrap();
+        assert_eq!(
+            map["imports"]["highlight-it"],
+            "https://cdn.example/script.js"
+        );
+        assert_eq!(
+            map["integrity"]["https://cdn.example/script.js"],
+            "sha512-script"
+        );
+        assert!(snippet.contains("import HighlightIt from 'highlight-it';"));
+    }
+}
@@ -0,0 +1,121 @@
+use listenfd::ListenFd;
+#[cfg(unix)]
+use std::fs;
+use std::io;
+use std::net::TcpListener;
+#[cfg(unix)]
+use std::os::unix::fs::{PermissionsExt, chown};
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+#[cfg(unix)]
+use std::path::Path;
+
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+/// Takes the sockets passed by systemd through `LISTEN_FDS`, in order.
+pub fn activated_listeners() -> io::Result<Vec<Listener>> {
+    let mut listen_fd = ListenFd::from_env();
+    let mut listeners = Vec::new();
+
+    for index in 0..listen_fd.len() {
+        if let Ok(Some(listener)) = listen_fd.take_tcp_listener(index) {
+            listeners.push(Listener::Tcp(listener));
+            continue;
+        }
+
+        #[cfg(unix)]
+        if let Some(listener) = listen_fd.take_unix_listener(index)? {
+            listeners.push(Listener::Unix(listener));
+        }
+    }
+
+    Ok(listeners)
+}
+
+/// Binds a Unix socket, replacing a stale socket file left by a previous
+/// run, and applies the configured mode and `user:group` owner.
+#[cfg(unix)]
+pub fn bind_unix_socket(
+    path: &str,
+    mode: Option<&str>,
+    owner: Option<&str>,
+) -> io::Result<Listener> {
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if metadata.file_type().is_file() || metadata.file_type().is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path),
+            ));
+        }
+        fs::remove_file(path)?;
+    }
+
+    let listener = UnixListener::bind(path)?;
+
+    if let Some(mode) = mode {
+        let mode = u32::from_str_radix(mode.trim_start_matches("0o"), 8).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid socket mode: {}", mode),
+            )
+        })?;
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
+
+    if let Some(owner) = owner {
+        let (user, group) = owner.split_once(':').unwrap_or((owner, ""));
+        let uid = resolve_id(user, "/etc/passwd")?;
+        let gid = resolve_id(group, "/etc/group")?;
+        chown(path, uid, gid)?;
+    }
+
+    Ok(Listener::Unix(listener))
+}
+
+/// Unix sockets are not available on this platform.
+#[cfg(not(unix))]
+pub fn bind_unix_socket(
+    path: &str,
+    _mode: Option<&str>,
+    _owner: Option<&str>,
+) -> io::Result<Listener> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!(
+            "Cannot bind {}, Unix sockets are not supported on this platform",
+            path
+        ),
+    ))
+}
+
+/// Resolves a user or group name to its id through the given database file.
+/// Numeric ids are used as is and an empty name leaves the id unchanged.
+#[cfg(unix)]
+fn resolve_id(name: &str, database: &str) -> io::Result<Option<u32>> {
+    if name.is_empty() {
+        return Ok(None);
+    }
+
+    if let Ok(id) = name.parse::<u32>() {
+        return Ok(Some(id));
+    }
+
+    let content = fs::read_to_string(Path::new(database))?;
+
+    content
+        .lines()
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .find(|fields| fields.first() == Some(&name))
+        .and_then(|fields| fields.get(2).and_then(|id| id.parse().ok()))
+        .map(Some)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} not found in {}", name, database),
+            )
+        })
+}
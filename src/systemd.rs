@@ -0,0 +1,162 @@
+use crate::INDEX_TEMPLATE;
+use crate::asset_manager::AssetManager;
+use crate::version_checker::VersionChecker;
+use actix_web::dev::ServerHandle;
+use std::env;
+use std::future::Future;
+use std::io;
+#[cfg(target_os = "linux")]
+use std::os::linux::net::SocketAddrExt;
+#[cfg(target_os = "linux")]
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// How often the status is refreshed when the watchdog is disabled
+const STATUS_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long a health check may take before the watchdog ping is skipped
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Sends a state update to the service manager through `NOTIFY_SOCKET`.
+/// Does nothing when the process was not started by systemd.
+#[cfg(target_os = "linux")]
+pub fn notify(state: &str) -> io::Result<()> {
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return Ok(());
+    };
+
+    let path = path.to_string_lossy();
+    let address = match path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(path.as_ref())?,
+    };
+
+    let socket = UnixDatagram::unbound()?;
+    socket.send_to_addr(state.as_bytes(), &address)?;
+
+    Ok(())
+}
+
+/// systemd only runs on Linux, there is no service manager to notify.
+#[cfg(not(target_os = "linux"))]
+pub fn notify(_state: &str) -> io::Result<()> {
+    Ok(())
+}
+
+/// Logs instead of failing, a lost notification must not stop the server.
+fn send(state: &str) {
+    if let Err(e) = notify(state) {
+        warn!(error = %e, "Failed to notify systemd");
+    }
+}
+
+/// Interval at which systemd expects `WATCHDOG=1`, if the watchdog is
+/// enabled for this process.
+fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(std::process::id()) {
+            return None;
+        }
+    }
+
+    env::var("WATCHDOG_USEC")
+        .ok()
+        .and_then(|usec| usec.parse::<u64>().ok())
+        .filter(|usec| *usec > 0)
+        .map(Duration::from_micros)
+}
+
+fn status_line(version: &str) -> String {
+    if version.is_empty() {
+        "STATUS=Waiting for highlight-it versions".to_string()
+    } else {
+        format!("STATUS=Serving highlight-it {}", version)
+    }
+}
+
+/// Checks that the version state and templates can still be read, which
+/// fails if a lock is held forever or the runtime is starved.
+async fn is_healthy(version_checker: &VersionChecker, asset_manager: &AssetManager) -> bool {
+    let check = async {
+        version_checker.get_current_version_info().await;
+        asset_manager.get_template(INDEX_TEMPLATE).await.is_some()
+    };
+
+    tokio::time::timeout(HEALTH_CHECK_TIMEOUT, check)
+        .await
+        .unwrap_or(false)
+}
+
+/// Reports readiness with the current version, then keeps the status up to
+/// date and pings the watchdog for as long as the health check passes.
+pub async fn notify_ready(version_checker: VersionChecker, asset_manager: AssetManager) {
+    let mut version = version_checker.get_current_version_info().await.version;
+    send(&format!("READY=1\n{}", status_line(&version)));
+
+    let watchdog = watchdog_interval();
+    let interval = watchdog.map_or(STATUS_INTERVAL, |interval| interval / 2);
+
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(interval).await;
+
+            if watchdog.is_some() && is_healthy(&version_checker, &asset_manager).await {
+                send("WATCHDOG=1");
+            }
+
+            let current = version_checker.get_current_version_info().await.version;
+            if current != version {
+                send(&status_line(&current));
+                version = current;
+            }
+        }
+    });
+}
+
+/// Stops accepting connections on SIGTERM or SIGINT and lets in-flight
+/// requests finish within the server's shutdown timeout.
+pub fn shutdown_on_terminate(handles: Vec<ServerHandle>) -> io::Result<()> {
+    let terminated = terminate_signal()?;
+
+    tokio::spawn(async move {
+        terminated.await;
+
+        info!("Shutting down, draining in-flight requests");
+        send("STOPPING=1\nSTATUS=Draining in-flight requests");
+
+        let stopping: Vec<_> = handles.iter().map(|handle| handle.stop(true)).collect();
+        for stopped in stopping {
+            stopped.await;
+        }
+    });
+
+    Ok(())
+}
+
+/// Resolves on the first SIGTERM or SIGINT.
+#[cfg(unix)]
+fn terminate_signal() -> io::Result<impl Future<Output = ()>> {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+
+    Ok(async move {
+        tokio::select! {
+            _ = terminate.recv() => {}
+            _ = interrupt.recv() => {}
+        }
+    })
+}
+
+/// Resolves on the first Ctrl-C, the only termination signal available.
+#[cfg(not(unix))]
+fn terminate_signal() -> io::Result<impl Future<Output = ()>> {
+    Ok(async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            warn!(error = %e, "Failed to listen for Ctrl-C");
+            std::future::pending::<()>().await;
+        }
+    })
+}
@@ -0,0 +1,498 @@
+use std::collections::HashMap;
+use std::fmt;
+
+/// Attributes whose values are URLs, so placeholders in them are
+/// percent-encoded before being HTML-escaped.
+const URL_ATTRIBUTES: [&str; 5] = ["href", "src", "action", "formaction", "poster"];
+
+/// Elements whose content is not HTML, where placeholders are rejected.
+const RAW_TEXT_ELEMENTS: [&str; 2] = ["script", "style"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Escape {
+    Html,
+    Attribute,
+    Url,
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Literal(String),
+    Variable { name: String, escape: Escape },
+}
+
+#[derive(Debug)]
+pub enum TemplateError {
+    /// A `{{` without a matching `}}`
+    Unterminated { offset: usize },
+    /// A placeholder whose name is not a lowercase identifier
+    InvalidName { offset: usize, name: String },
+    /// A placeholder outside of text or a quoted attribute value
+    UnsupportedContext { offset: usize, name: String },
+    /// A variable used by the template that the renderer does not provide
+    MissingVariable { name: String },
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::Unterminated { offset } => {
+                write!(f, "unterminated placeholder at byte {}", offset)
+            }
+            TemplateError::InvalidName { offset, name } => {
+                write!(f, "invalid placeholder name '{}' at byte {}", name, offset)
+            }
+            TemplateError::UnsupportedContext { offset, name } => write!(
+                f,
+                "placeholder '{}' at byte {} is not in text or a quoted attribute value",
+                name, offset
+            ),
+            TemplateError::MissingVariable { name } => {
+                write!(f, "variable '{}' is not provided", name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+enum Value {
+    Text(String),
+    Html(String),
+}
+
+/// Values available to a template while rendering.
+#[derive(Default)]
+pub struct TemplateContext {
+    values: HashMap<&'static str, Value>,
+}
+
+impl TemplateContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a plain text value, escaped for the context it is used in.
+    pub fn insert(&mut self, name: &'static str, value: impl Into<String>) -> &mut Self {
+        self.values.insert(name, Value::Text(value.into()));
+        self
+    }
+
+    /// Adds trusted markup, inserted unescaped in text and escaped elsewhere.
+    pub fn insert_html(&mut self, name: &'static str, value: impl Into<String>) -> &mut Self {
+        self.values.insert(name, Value::Html(value.into()));
+        self
+    }
+}
+
+/// State of the HTML scanner at the current position of the template.
+#[derive(Clone, PartialEq)]
+enum Scan {
+    Text,
+    Comment,
+    Tag {
+        name: String,
+        attribute: String,
+        in_attribute: bool,
+    },
+    AttributeValue {
+        name: String,
+        attribute: String,
+        quote: char,
+    },
+    RawText {
+        name: String,
+    },
+}
+
+/// A template parsed into literal text and `{{ name }}` placeholders, each
+/// with the escaping required by its position in the HTML document.
+#[derive(Debug, Clone)]
+pub struct CompiledTemplate {
+    segments: Vec<Segment>,
+}
+
+impl CompiledTemplate {
+    pub fn compile(source: &str) -> Result<Self, TemplateError> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut scan = Scan::Text;
+        let mut offset = 0;
+
+        while offset < source.len() {
+            let rest = &source[offset..];
+
+            if rest.starts_with("{{") {
+                let end = rest
+                    .find("}}")
+                    .ok_or(TemplateError::Unterminated { offset })?;
+                let name = rest[2..end].trim().to_string();
+
+                if !is_valid_name(&name) {
+                    return Err(TemplateError::InvalidName { offset, name });
+                }
+
+                let escape = match &scan {
+                    Scan::Text => Escape::Html,
+                    Scan::AttributeValue { attribute, .. }
+                        if URL_ATTRIBUTES.contains(&attribute.as_str()) =>
+                    {
+                        Escape::Url
+                    }
+                    Scan::AttributeValue { .. } => Escape::Attribute,
+                    _ => return Err(TemplateError::UnsupportedContext { offset, name }),
+                };
+
+                if !literal.is_empty() {
+                    segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                }
+                segments.push(Segment::Variable { name, escape });
+
+                offset += end + 2;
+                continue;
+            }
+
+            let character = rest.chars().next().unwrap_or_default();
+            scan = advance(scan, rest, character);
+            literal.push(character);
+            offset += character.len_utf8();
+        }
+
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        Ok(Self { segments })
+    }
+
+    /// Names of all variables the template uses.
+    pub fn variables(&self) -> impl Iterator<Item = &str> {
+        self.segments.iter().filter_map(|segment| match segment {
+            Segment::Variable { name, .. } => Some(name.as_str()),
+            Segment::Literal(_) => None,
+        })
+    }
+
+    /// Checks that every variable the template uses is provided by
+    /// `context`, so a template referring to a value the renderer does not
+    /// set is rejected at startup rather than on the first request.
+    pub fn validate(&self, context: &TemplateContext) -> Result<(), TemplateError> {
+        match self
+            .variables()
+            .find(|name| !context.values.contains_key(name))
+        {
+            Some(name) => Err(TemplateError::MissingVariable {
+                name: name.to_string(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    pub fn render(&self, context: &TemplateContext) -> Result<String, TemplateError> {
+        let mut output = String::new();
+
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(text) => output.push_str(text),
+                Segment::Variable { name, escape } => {
+                    let value = context
+                        .values
+                        .get(name.as_str())
+                        .ok_or_else(|| TemplateError::MissingVariable { name: name.clone() })?;
+
+                    match (value, escape) {
+                        (Value::Html(markup), Escape::Html) => output.push_str(markup),
+                        (Value::Text(text) | Value::Html(text), Escape::Url) => {
+                            output.push_str(&escape_html(&escape_url(text)))
+                        }
+                        (Value::Text(text) | Value::Html(text), _) => {
+                            output.push_str(&escape_html(text))
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(output)
+    }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    let mut characters = name.chars();
+
+    characters
+        .next()
+        .is_some_and(|first| first.is_ascii_lowercase() || first == '_')
+        && characters.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+/// Moves the scanner past `character`, which starts `rest`.
+fn advance(scan: Scan, rest: &str, character: char) -> Scan {
+    match scan {
+        Scan::Text if rest.starts_with("<!--") => Scan::Comment,
+        Scan::Text if character == '<' => {
+            let name: String = rest[1..]
+                .chars()
+                .take_while(|c| c.is_ascii_alphanumeric() || *c == '/')
+                .collect();
+
+            if name.is_empty() {
+                Scan::Text
+            } else {
+                Scan::Tag {
+                    name: name.to_ascii_lowercase(),
+                    attribute: String::new(),
+                    in_attribute: false,
+                }
+            }
+        }
+        Scan::Comment if rest.starts_with("-->") => Scan::Text,
+        Scan::Tag { name, .. } if character == '>' => {
+            if RAW_TEXT_ELEMENTS.contains(&name.as_str()) {
+                Scan::RawText { name }
+            } else {
+                Scan::Text
+            }
+        }
+        Scan::Tag {
+            name, attribute, ..
+        } if character == '"' || character == '\'' => Scan::AttributeValue {
+            name,
+            attribute,
+            quote: character,
+        },
+        Scan::Tag {
+            name,
+            mut attribute,
+            in_attribute,
+        } => {
+            let is_name_character = character.is_ascii_alphanumeric() || character == '-';
+
+            if is_name_character && !in_attribute {
+                attribute.clear();
+            }
+            if is_name_character {
+                attribute.push(character.to_ascii_lowercase());
+            }
+
+            Scan::Tag {
+                name,
+                attribute,
+                in_attribute: is_name_character,
+            }
+        }
+        Scan::AttributeValue { name, quote, .. } if character == quote => Scan::Tag {
+            name,
+            attribute: String::new(),
+            in_attribute: false,
+        },
+        Scan::RawText { name }
+            if character == '<'
+                && rest
+                    .get(1..name.len() + 2)
+                    .is_some_and(|closing| closing.eq_ignore_ascii_case(&format!("/{}", name))) =>
+        {
+            Scan::Tag {
+                name: format!("/{}", name),
+                attribute: String::new(),
+                in_attribute: false,
+            }
+        }
+        scan => scan,
+    }
+}
+
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for character in text.chars() {
+        match character {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(character),
+        }
+    }
+
+    escaped
+}
+
+/// Percent-encodes everything except unreserved characters and `/`, so a
+/// value can only ever form path segments of the surrounding URL.
+pub fn escape_url(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                escaped.push(byte as char)
+            }
+            _ => escaped.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(source: &str, value: &str) -> Result<String, TemplateError> {
+        let mut context = TemplateContext::new();
+        context.insert("value", value);
+        CompiledTemplate::compile(source)?.render(&context)
+    }
+
+    #[test]
+    fn escapes_text() {
+        assert_eq!(
+            render("<p>{{ value }}</p>", "<b>\"Tom\" & 'Jerry'</b>").unwrap(),
+            "<p>&lt;b&gt;&quot;Tom&quot; &amp; &#39;Jerry&#39;&lt;/b&gt;</p>"
+        );
+    }
+
+    #[test]
+    fn inserts_trusted_markup_only_in_text() {
+        let template = CompiledTemplate::compile(
+            "<div title=\"{{ markup }}\">{{ markup }}</div><a href=\"{{ markup }}\">",
+        )
+        .unwrap();
+        let mut context = TemplateContext::new();
+        context.insert_html("markup", "<b>1</b>");
+
+        assert_eq!(
+            template.render(&context).unwrap(),
+            "<div title=\"&lt;b&gt;1&lt;/b&gt;\"><b>1</b></div><a href=\"%3Cb%3E1%3C/b%3E\">"
+        );
+    }
+
+    #[test]
+    fn escapes_attributes() {
+        assert_eq!(
+            render("<img alt=\"{{ value }}\">", "\" onerror=\"alert(1)").unwrap(),
+            "<img alt=\"&quot; onerror=&quot;alert(1)\">"
+        );
+        assert_eq!(
+            render("<img alt='{{ value }}'>", "' onerror='alert(1)").unwrap(),
+            "<img alt='&#39; onerror=&#39;alert(1)'>"
+        );
+        assert_eq!(
+            render(
+                "<input DATA-X=\"{{ value }}\" value=\"{{ value }}\">",
+                "a&b"
+            )
+            .unwrap(),
+            "<input DATA-X=\"a&amp;b\" value=\"a&amp;b\">"
+        );
+    }
+
+    #[test]
+    fn percent_encodes_urls() {
+        assert_eq!(
+            render("<a href=\"/static/{{ value }}/main.css\">", "1.2.3").unwrap(),
+            "<a href=\"/static/1.2.3/main.css\">"
+        );
+        assert_eq!(
+            render("<script src=\"/{{ value }}\"></script>", "a b?c=1&d=\"2\"").unwrap(),
+            "<script src=\"/a%20b%3Fc%3D1%26d%3D%222%22\"></script>"
+        );
+        assert_eq!(
+            render("<a HREF='{{ value }}'>", "javascript:alert(1)").unwrap(),
+            "<a HREF='javascript%3Aalert%281%29'>"
+        );
+        assert_eq!(
+            render("<a href=\"{{ value }}\">", "ü").unwrap(),
+            "<a href=\"%C3%BC\">"
+        );
+    }
+
+    #[test]
+    fn rejects_script_and_style_content() {
+        for source in [
+            "<script>const version = '{{ value }}';</script>",
+            "<script type=\"module\">{{ value }}</script>",
+            "<SCRIPT>{{ value }}</SCRIPT>",
+            "<style>body { color: {{ value }}; }</style>",
+        ] {
+            assert!(
+                matches!(
+                    CompiledTemplate::compile(source),
+                    Err(TemplateError::UnsupportedContext { .. })
+                ),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn allows_script_attributes_and_text_after_script() {
+        assert_eq!(
+            render(
+                "<script nonce=\"{{ value }}\">let a = '</p>';</script><p>{{ value }}</p>",
+                "<n>"
+            )
+            .unwrap(),
+            "<script nonce=\"&lt;n&gt;\">let a = '</p>';</script><p>&lt;n&gt;</p>"
+        );
+    }
+
+    #[test]
+    fn rejects_other_contexts() {
+        for source in [
+            "<div {{ value }}>",
+            "<div class={{ value }}>",
+            "<!-- {{ value }} -->",
+        ] {
+            assert!(
+                matches!(
+                    CompiledTemplate::compile(source),
+                    Err(TemplateError::UnsupportedContext { .. })
+                ),
+                "{}",
+                source
+            );
+        }
+        assert_eq!(
+            render("<!-- <p> --><p>{{ value }}</p>", "<").unwrap(),
+            "<!-- <p> --><p>&lt;</p>"
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_placeholders() {
+        assert!(matches!(
+            CompiledTemplate::compile("<p>{{ value </p>"),
+            Err(TemplateError::Unterminated { offset: 3 })
+        ));
+        for name in ["Value", "1value", "value-name", "", "a b"] {
+            assert!(
+                matches!(
+                    CompiledTemplate::compile(&format!("{{{{ {} }}}}", name)),
+                    Err(TemplateError::InvalidName { .. })
+                ),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn validates_against_context() {
+        let template = CompiledTemplate::compile("<p>{{ value }} {{ other }}</p>").unwrap();
+        let mut context = TemplateContext::new();
+        context.insert("value", "");
+
+        assert!(matches!(
+            template.validate(&context),
+            Err(TemplateError::MissingVariable { name }) if name == "other"
+        ));
+        assert!(template.render(&context).is_err());
+
+        context.insert("other", "");
+        assert!(template.validate(&context).is_ok());
+    }
+}
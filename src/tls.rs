@@ -0,0 +1,129 @@
+use crate::config::Config;
+use actix_web::http::header::LOCATION;
+use actix_web::{HttpRequest, HttpResponse, web};
+use rustls::ServerConfig;
+use rustls::crypto::ring::{default_provider, sign::any_supported_type};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use std::io;
+use std::sync::{Arc, RwLock};
+use tokio::signal::unix::{SignalKind, signal};
+use tracing::{error, info};
+
+/// Serves the certificate most recently loaded from disk. Reloading swaps
+/// the certificate for new handshakes while open connections keep theirs.
+#[derive(Debug)]
+pub struct CertificateResolver {
+    cert_path: String,
+    key_path: String,
+    certified_key: RwLock<Arc<CertifiedKey>>,
+}
+
+impl CertificateResolver {
+    pub fn new(cert_path: &str, key_path: &str) -> io::Result<Self> {
+        Ok(Self {
+            cert_path: cert_path.to_string(),
+            key_path: key_path.to_string(),
+            certified_key: RwLock::new(Arc::new(load_certified_key(cert_path, key_path)?)),
+        })
+    }
+
+    pub fn reload(&self) -> io::Result<()> {
+        let certified_key = load_certified_key(&self.cert_path, &self.key_path)?;
+
+        let mut current = self
+            .certified_key
+            .write()
+            .map_err(|_| io::Error::other("Certificate lock poisoned"))?;
+        *current = Arc::new(certified_key);
+
+        Ok(())
+    }
+}
+
+impl ResolvesServerCert for CertificateResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        self.certified_key.read().ok().map(|key| Arc::clone(&key))
+    }
+}
+
+fn load_certified_key(cert_path: &str, key_path: &str) -> io::Result<CertifiedKey> {
+    let invalid = |e: rustls::pki_types::pem::Error| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("{}", e))
+    };
+
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .map_err(invalid)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(invalid)?;
+
+    if certs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("No certificates found in {}", cert_path),
+        ));
+    }
+
+    let key = PrivateKeyDer::from_pem_file(key_path).map_err(invalid)?;
+    let signing_key =
+        any_supported_type(&key).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    Ok(CertifiedKey::new(certs, signing_key))
+}
+
+/// Builds the rustls configuration. actix-web advertises `h2` and
+/// `http/1.1` through ALPN on top of it.
+pub fn build_server_config(resolver: Arc<CertificateResolver>) -> io::Result<ServerConfig> {
+    let config = ServerConfig::builder_with_provider(Arc::new(default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(io::Error::other)?
+        .with_no_client_auth()
+        .with_cert_resolver(resolver);
+
+    Ok(config)
+}
+
+/// Reloads the certificate and key from disk whenever SIGHUP is received.
+pub fn reload_on_hangup(resolver: Arc<CertificateResolver>) -> io::Result<()> {
+    let mut hangup = signal(SignalKind::hangup())?;
+
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            match resolver.reload() {
+                Ok(()) => info!("Reloaded TLS certificate"),
+                Err(e) => error!(error = %e, "Failed to reload TLS certificate"),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Answers every request on the plain HTTP listener with a permanent
+/// redirect to the same path on the HTTPS listener.
+pub async fn redirect_to_https(req: HttpRequest, config: web::Data<Config>) -> HttpResponse {
+    let host = req.connection_info().host().to_string();
+    let hostname = match host.rsplit_once(':') {
+        Some((hostname, port)) if !hostname.is_empty() && port.parse::<u16>().is_ok() => {
+            hostname.to_string()
+        }
+        _ => host,
+    };
+
+    let authority = if config.port == 443 {
+        hostname
+    } else {
+        format!("{}:{}", hostname, config.port)
+    };
+
+    let path = req
+        .uri()
+        .path_and_query()
+        .map_or("/", |path_and_query| path_and_query.as_str());
+
+    HttpResponse::MovedPermanently()
+        .insert_header((LOCATION, format!("https://{}{}", authority, path)))
+        .finish()
+}
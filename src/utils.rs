@@ -1,6 +1,48 @@
-use crate::asset_manager::AssetManager;
+use crate::asset_manager::{AssetManager, EncodedContent};
+use crate::rate_limit::{RateLimiter, RouteGroup};
+use crate::security::{request_nonce, request_nonce_issued};
+use crate::template::TemplateContext;
 use crate::version_checker::VersionChecker;
-use actix_web::{HttpRequest, HttpResponse, Responder, web};
+use actix_web::http::StatusCode;
+use actix_web::http::header::{
+    self, Accept, AcceptEncoding, ContentEncoding, ETag, Encoding, EntityTag, HttpDate,
+    IfModifiedSince, IfNoneMatch, LastModified, Quality,
+};
+use actix_web::{HttpMessage, HttpRequest, HttpResponse, HttpResponseBuilder, Responder, web};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const NOT_FOUND_TEMPLATE: &str = "404.min.html";
+
+/// Seconds clients are asked to wait when no version is loaded yet, such as
+/// right after a cold start
+pub const NOT_READY_RETRY_AFTER: u64 = 10;
+
+/// A 503 for pages requested before any version is loaded, so that a broken
+/// page is never served or cached.
+pub fn create_not_ready_response() -> HttpResponse {
+    HttpResponse::ServiceUnavailable()
+        .content_type("text/plain")
+        .append_header((header::RETRY_AFTER, NOT_READY_RETRY_AFTER))
+        .append_header(("Cache-Control", "no-store"))
+        .body("No version is available yet\n")
+}
+
+/// Values of the not found template, also used to validate it at startup.
+pub fn not_found_context(
+    version: String,
+    sri_hash: String,
+    path: String,
+    csp_nonce: String,
+) -> TemplateContext {
+    let mut context = TemplateContext::new();
+    context
+        .insert("demo_version", get_hit_demo_version())
+        .insert("version", version)
+        .insert("sri_hash", sri_hash)
+        .insert("path", path)
+        .insert("csp_nonce", csp_nonce);
+    context
+}
 
 pub fn get_hit_demo_version() -> String {
     env!("CARGO_PKG_VERSION").to_string()
@@ -13,9 +55,27 @@ pub fn get_cdn_url(version: &str) -> String {
     )
 }
 
+/// Other CDNs serving the same file, so the same SRI hash applies
+pub fn get_mirror_urls(version: &str) -> Vec<String> {
+    vec![
+        format!(
+            "https://fastly.jsdelivr.net/npm/highlight-it@{}/dist/highlight-it-min.js",
+            version
+        ),
+        format!(
+            "https://unpkg.com/highlight-it@{}/dist/highlight-it-min.js",
+            version
+        ),
+    ]
+}
+
 pub fn get_current_datetime() -> String {
-    let now = std::time::SystemTime::now();
-    let duration = now
+    format_datetime(SystemTime::now())
+}
+
+/// Formats a timestamp as RFC 3339 in UTC
+pub fn format_datetime(time: SystemTime) -> String {
+    let duration = time
         .duration_since(std::time::UNIX_EPOCH)
         .unwrap_or_default();
 
@@ -80,13 +140,261 @@ pub fn get_current_datetime() -> String {
     )
 }
 
+/// Parses an RFC 3339 timestamp such as "2025-03-01T12:34:56.789Z", or a
+/// bare date meaning midnight UTC. Times before 1970 are rejected.
+pub fn parse_datetime(text: &str) -> Option<SystemTime> {
+    let number = |part: &str| -> Option<u64> {
+        (!part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()))
+            .then(|| part.parse().ok())
+            .flatten()
+    };
+
+    let (date, time) = match text.split_once(['T', 't', ' ']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (text, None),
+    };
+
+    let mut date_parts = date.splitn(3, '-');
+    let year = number(date_parts.next()?)?;
+    let month = number(date_parts.next()?)?;
+    let day = number(date_parts.next()?)?;
+
+    if year < 1970 || !(1..=12).contains(&month) {
+        return None;
+    }
+
+    let is_leap_year = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let month_days = [
+        31,
+        if is_leap_year { 29 } else { 28 },
+        31,
+        30,
+        31,
+        30,
+        31,
+        31,
+        30,
+        31,
+        30,
+        31,
+    ];
+    if day == 0 || day > month_days[month as usize - 1] {
+        return None;
+    }
+
+    let days_before_year: u64 = (1970..year)
+        .map(|y| {
+            if y % 4 == 0 && (y % 100 != 0 || y % 400 == 0) {
+                366
+            } else {
+                365
+            }
+        })
+        .sum();
+    let days_before_month: u64 = month_days[..month as usize - 1].iter().sum();
+    let mut seconds = (days_before_year + days_before_month + day - 1) * 86400;
+
+    if let Some(time) = time {
+        let (clock, offset) = match time.find(['Z', 'z', '+', '-']) {
+            Some(index) => time.split_at(index),
+            None => return None,
+        };
+        let clock = clock.split_once('.').map_or(clock, |(clock, fraction)| {
+            if number(fraction).is_some() {
+                clock
+            } else {
+                ""
+            }
+        });
+
+        let mut clock_parts = clock.splitn(3, ':');
+        let hours = number(clock_parts.next()?)?;
+        let minutes = number(clock_parts.next()?)?;
+        let secs = number(clock_parts.next()?)?;
+        if hours > 23 || minutes > 59 || secs > 60 {
+            return None;
+        }
+        seconds += hours * 3600 + minutes * 60 + secs;
+
+        if !offset.eq_ignore_ascii_case("z") {
+            let (sign, offset) = offset.split_at(1);
+            let (offset_hours, offset_minutes) = offset.split_once(':')?;
+            let offset = number(offset_hours)? * 3600 + number(offset_minutes)? * 60;
+            seconds = match sign {
+                "+" => seconds.checked_sub(offset)?,
+                _ => seconds + offset,
+            };
+        }
+    }
+
+    Some(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+/// Truncates a timestamp to whole seconds, the precision of HTTP dates.
+fn truncate_to_seconds(time: SystemTime) -> SystemTime {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    UNIX_EPOCH + Duration::from_secs(seconds)
+}
+
+/// Checks the request's `If-None-Match` and `If-Modified-Since` headers against
+/// the representation. `If-Modified-Since` is only evaluated when no
+/// `If-None-Match` header was sent.
+fn is_not_modified(req: &HttpRequest, etag: &EntityTag, last_modified: SystemTime) -> bool {
+    if let Some(if_none_match) = req.get_header::<IfNoneMatch>() {
+        return match if_none_match {
+            IfNoneMatch::Any => true,
+            IfNoneMatch::Items(tags) => tags.iter().any(|tag| tag.weak_eq(etag)),
+        };
+    }
+
+    if let Some(IfModifiedSince(since)) = req.get_header::<IfModifiedSince>() {
+        return truncate_to_seconds(last_modified) <= SystemTime::from(since);
+    }
+
+    false
+}
+
+/// Finishes a response with the precompressed variant of `content` that best
+/// matches the request's `Accept-Encoding`. The explicit `Content-Encoding`
+/// keeps the compression middleware from encoding the body a second time.
+/// Conditional requests matching the ETag or `last_modified` get a 304.
+pub fn create_encoded_response(
+    req: &HttpRequest,
+    mut builder: HttpResponseBuilder,
+    content: &EncodedContent,
+    last_modified: SystemTime,
+) -> HttpResponse {
+    let mut available = vec![Encoding::identity()];
+    if content.brotli.is_some() {
+        available.push(Encoding::brotli());
+    }
+    if content.zstd.is_some() {
+        available.push(Encoding::zstd());
+    }
+    if content.gzip.is_some() {
+        available.push(Encoding::gzip());
+    }
+
+    let selected = req
+        .get_header::<AcceptEncoding>()
+        .and_then(|accept_encoding| accept_encoding.negotiate(available.iter()))
+        .unwrap_or_else(Encoding::identity);
+
+    let (encoding, body) = match selected {
+        Encoding::Known(ContentEncoding::Brotli) => (ContentEncoding::Brotli, &content.brotli),
+        Encoding::Known(ContentEncoding::Zstd) => (ContentEncoding::Zstd, &content.zstd),
+        Encoding::Known(ContentEncoding::Gzip) => (ContentEncoding::Gzip, &content.gzip),
+        _ => (ContentEncoding::Identity, &None),
+    };
+
+    let etag = match encoding {
+        ContentEncoding::Identity => EntityTag::new_strong(content.hash.clone()),
+        _ => EntityTag::new_strong(format!("{}-{}", content.hash, encoding.as_str())),
+    };
+
+    builder
+        .insert_header(encoding)
+        .append_header((header::VARY, "Accept-Encoding"))
+        .insert_header(ETag(etag.clone()))
+        .insert_header(LastModified(HttpDate::from(last_modified)));
+
+    if is_not_modified(req, &etag, last_modified) {
+        return builder.status(StatusCode::NOT_MODIFIED).finish();
+    }
+
+    builder.body(body.clone().unwrap_or_else(|| content.identity.clone()))
+}
+
+/// Finishes a page rendered with the request's CSP nonce. All pages of a
+/// nonce epoch share the nonce, so the page is cached and revalidated like
+/// an asset: its ETag covers the nonce and it is never older than the epoch.
+pub async fn create_page_response(
+    req: &HttpRequest,
+    builder: HttpResponseBuilder,
+    asset_manager: &AssetManager,
+    content: String,
+    last_modified: SystemTime,
+) -> HttpResponse {
+    let encoded = asset_manager.encode_rendered(content).await;
+    let last_modified = last_modified.max(request_nonce_issued(req));
+
+    create_encoded_response(req, builder, &encoded, last_modified)
+}
+
+/// Representations of a versioned demo page
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PageFormat {
+    Html,
+    Json,
+    Markdown,
+}
+
+/// The representation the client prefers by its `Accept` header, HTML if it
+/// does not say. `None` if it accepts none of them.
+pub fn negotiate_page_format(req: &HttpRequest) -> Option<PageFormat> {
+    let Some(accept) = req
+        .get_header::<Accept>()
+        .filter(|accept| !accept.is_empty())
+    else {
+        return Some(PageFormat::Html);
+    };
+
+    let refused: Vec<_> = accept
+        .iter()
+        .filter(|preference| preference.quality == Quality::ZERO)
+        .map(|preference| &preference.item)
+        .collect();
+    // A wildcard only stands for the formats no refusal matches
+    let is_refused = |format: &PageFormat| {
+        let (type_, subtype) = match format {
+            PageFormat::Html => ("text", "html"),
+            PageFormat::Json => ("application", "json"),
+            PageFormat::Markdown => ("text", "markdown"),
+        };
+        refused.iter().any(|mime| {
+            [type_, "*"].contains(&mime.type_().as_str())
+                && [subtype, "*"].contains(&mime.subtype().as_str())
+        })
+    };
+
+    accept
+        .ranked()
+        .iter()
+        .filter(|mime| !refused.contains(mime))
+        .find_map(|mime| {
+            let candidates: &[PageFormat] = match mime.essence_str() {
+                "text/html" | "application/xhtml+xml" => return Some(PageFormat::Html),
+                "application/json" => return Some(PageFormat::Json),
+                "text/markdown" => return Some(PageFormat::Markdown),
+                "text/*" => &[PageFormat::Html, PageFormat::Markdown],
+                "application/*" => &[PageFormat::Json],
+                "*/*" => &[PageFormat::Html, PageFormat::Json, PageFormat::Markdown],
+                _ => &[],
+            };
+            candidates
+                .iter()
+                .find(|format| !is_refused(format))
+                .copied()
+        })
+}
+
 pub async fn create_not_found_response(
+    req: &HttpRequest,
     reason: &str,
     version_checker: web::Data<VersionChecker>,
     asset_manager: web::Data<AssetManager>,
     path: Option<&str>,
 ) -> HttpResponse {
-    if let Some(content) = asset_manager.get_template("404.min.html").await {
+    if let Some(limiter) = req.app_data::<web::Data<RateLimiter>>() {
+        if let Some(response) = limiter.check(req, RouteGroup::NotFound) {
+            return response;
+        }
+    }
+
+    if let Some(template) = asset_manager.get_template(NOT_FOUND_TEMPLATE).await {
         let version_info = version_checker.get_latest_version_info().await;
         let version = version_info.version;
         let sri_hash = version_info.sri_hash;
@@ -110,16 +418,20 @@ pub async fn create_not_found_response(
             format!("/{}", truncated_parts.join("/"))
         });
 
-        let content = content
-            .replace("DEMO_VERSION", &get_hit_demo_version())
-            .replace("VERSION", &version)
-            .replace("SRI_HASH", &sri_hash)
-            .replace("PATH", &formatted_path);
+        let rendered = template.compiled.render(&not_found_context(
+            version,
+            sri_hash,
+            formatted_path,
+            request_nonce(req),
+        ));
 
-        HttpResponse::NotFound()
-            .content_type("text/html")
-            .append_header(("Cache-Control", "public, max-age=60"))
-            .body(content)
+        match rendered {
+            Ok(content) => HttpResponse::NotFound()
+                .content_type("text/html")
+                .append_header(("Cache-Control", "public, max-age=60"))
+                .body(content),
+            Err(_) => HttpResponse::NotFound().body(format!("Page not found: {}", reason)),
+        }
     } else {
         HttpResponse::NotFound().body(format!("Page not found: {}", reason))
     }
@@ -132,6 +444,7 @@ pub async fn not_found_handler(
 ) -> impl Responder {
     let path = req.path();
     create_not_found_response(
+        &req,
         &format!("Path not found: {}", path),
         version_checker,
         asset_manager,
@@ -139,3 +452,110 @@ pub async fn not_found_handler(
     )
     .await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header::ACCEPT;
+    use actix_web::test::TestRequest;
+
+    fn at(seconds: u64) -> Option<SystemTime> {
+        Some(UNIX_EPOCH + Duration::from_secs(seconds))
+    }
+
+    #[test]
+    fn parses_datetimes() {
+        for (text, expected) in [
+            ("1970-01-01", at(0)),
+            ("1970-01-01T00:00:00Z", at(0)),
+            ("2025-03-01T12:34:56Z", at(1740832496)),
+            ("2025-03-01t12:34:56z", at(1740832496)),
+            ("2025-03-01 12:34:56Z", at(1740832496)),
+            ("2025-03-01T12:34:56.789Z", at(1740832496)),
+            ("2025-03-01T14:34:56+02:00", at(1740832496)),
+            ("2025-03-01T10:04:56-02:30", at(1740832496)),
+            ("2024-02-29", at(1709164800)),
+            ("2000-02-29", at(951782400)),
+        ] {
+            assert_eq!(parse_datetime(text), expected, "{}", text);
+        }
+
+        for text in [
+            "",
+            "yesterday",
+            "1969-12-31",
+            "1970-01-01T00:00:00+00:01",
+            "2025-13-01",
+            "2025-00-01",
+            "2025-02-29",
+            "1900-02-29",
+            "2025-03-00",
+            "2025-03-01T12:34:56",
+            "2025-03-01T24:00:00Z",
+            "2025-03-01T12:60:00Z",
+            "2025-03-01T12:34Z",
+            "2025-03-01T12:34:56.xZ",
+            "2025-03-01T12:34:56+0200",
+            "+2025-03-01",
+            "2025-3-1x",
+        ] {
+            assert_eq!(parse_datetime(text), None, "{}", text);
+        }
+    }
+
+    #[test]
+    fn negotiates_page_formats() {
+        use PageFormat::{Html, Json, Markdown};
+
+        for (accept, expected) in [
+            (None, Some(Html)),
+            (Some(""), Some(Html)),
+            (Some("text/html"), Some(Html)),
+            (Some("application/xhtml+xml"), Some(Html)),
+            (Some("application/json"), Some(Json)),
+            (Some("text/markdown"), Some(Markdown)),
+            (Some("text/html;q=0.5, application/json"), Some(Json)),
+            (
+                Some("application/json;q=0.5, text/markdown;q=0.9"),
+                Some(Markdown),
+            ),
+            (Some("image/png, application/json;q=0.1"), Some(Json)),
+            (Some("*/*, application/json"), Some(Json)),
+            (Some("text/*, application/json;q=0.9"), Some(Html)),
+            (Some("*/*"), Some(Html)),
+            (Some("text/*"), Some(Html)),
+            (Some("application/*"), Some(Json)),
+            (Some("text/html;q=0, */*"), Some(Json)),
+            (Some("text/*;q=0, */*"), Some(Json)),
+            (Some("text/html;q=0, text/*"), Some(Markdown)),
+            (Some("text/*;q=0, text/html"), Some(Html)),
+            (Some("application/json;q=0, application/*"), None),
+            (Some("*/*;q=0"), None),
+            (Some("text/html;q=0"), None),
+            (Some("image/png"), None),
+            (Some("text/plain, application/xml"), None),
+        ] {
+            let mut req = TestRequest::default();
+            if let Some(accept) = accept {
+                req = req.insert_header((ACCEPT, accept));
+            }
+            assert_eq!(
+                negotiate_page_format(&req.to_http_request()),
+                expected,
+                "{:?}",
+                accept
+            );
+        }
+    }
+
+    #[test]
+    fn formats_what_it_parses() {
+        for text in [
+            "1970-01-01T00:00:00+00:00",
+            "2025-03-01T12:34:56+00:00",
+            "2024-02-29T23:59:59+00:00",
+        ] {
+            assert_eq!(format_datetime(parse_datetime(text).unwrap()), text);
+        }
+    }
+}
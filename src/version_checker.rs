@@ -1,16 +1,19 @@
+use crate::metrics::Metrics;
+use crate::template::escape_html;
 use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
 use regex::Regex;
-use reqwest;
 use reqwest::header::{CACHE_CONTROL, EXPIRES, PRAGMA};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use sha2::{Digest, Sha512};
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::io;
 use std::path::Path;
 use std::sync::Arc;
-use std::time::Duration;
-use tokio::sync::RwLock;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::{RwLock, watch};
+use tracing::{debug, info, warn};
 
 const NO_CACHE_HEADERS: [(reqwest::header::HeaderName, &str); 3] = [
     (CACHE_CONTROL, "no-cache, no-store, must-revalidate"),
@@ -21,10 +24,21 @@ const NO_CACHE_HEADERS: [(reqwest::header::HeaderName, &str); 3] = [
 const VERSION_REGEX: &str = r"^[0-9]\.[0-9]{1,2}\.[0-9]{1,2}$";
 const CACHE_FILE_NAME: &str = "version_cache.json";
 
-#[derive(Clone, Serialize, Deserialize)]
+#[derive(Clone, Default, Serialize, Deserialize)]
 pub struct VersionInfo {
     pub version: String,
     pub sri_hash: String,
+    /// Publish time on npm as RFC 3339, if known
+    #[serde(default)]
+    pub published: Option<String>,
+    /// Deprecation message set on npm, if the version is deprecated
+    #[serde(default)]
+    pub deprecated: Option<String>,
+}
+
+struct VersionMetadata {
+    published: Option<String>,
+    deprecated: Option<String>,
 }
 
 pub struct VersionChecker {
@@ -33,6 +47,14 @@ pub struct VersionChecker {
     current_version_info: Arc<RwLock<VersionInfo>>,
     all_versions: Arc<RwLock<Vec<VersionInfo>>>,
     latest_version: Arc<RwLock<VersionInfo>>,
+    /// npm dist-tags such as "latest" and "next", by tag
+    dist_tags: Arc<RwLock<BTreeMap<String, String>>>,
+    /// Time of the last change to the cached version state, which
+    /// subscribers are notified of
+    updated_at: Arc<watch::Sender<SystemTime>>,
+    /// SRI hashes of other files on the CDN such as stylesheets, by URL
+    file_sri_hashes: Arc<RwLock<HashMap<String, String>>>,
+    metrics: Metrics,
     http_timeout_secs: u64,
     version_check_interval_secs: u64,
     cache_file_path: String,
@@ -44,6 +66,7 @@ impl VersionChecker {
         http_timeout_secs: u64,
         version_check_interval_secs: u64,
         cache_dir: Option<&str>,
+        metrics: Metrics,
     ) -> Self {
         let mut headers = reqwest::header::HeaderMap::new();
         for (key, value) in NO_CACHE_HEADERS.iter() {
@@ -68,15 +91,13 @@ impl VersionChecker {
         Self {
             client,
             package_name: package_name.to_string(),
-            current_version_info: Arc::new(RwLock::new(VersionInfo {
-                version: String::new(),
-                sri_hash: String::new(),
-            })),
+            current_version_info: Arc::new(RwLock::new(VersionInfo::default())),
             all_versions: Arc::new(RwLock::new(Vec::new())),
-            latest_version: Arc::new(RwLock::new(VersionInfo {
-                version: String::new(),
-                sri_hash: String::new(),
-            })),
+            latest_version: Arc::new(RwLock::new(VersionInfo::default())),
+            dist_tags: Arc::new(RwLock::new(BTreeMap::new())),
+            updated_at: Arc::new(watch::Sender::new(SystemTime::now())),
+            file_sri_hashes: Arc::new(RwLock::new(HashMap::new())),
+            metrics,
             http_timeout_secs,
             version_check_interval_secs,
             cache_file_path,
@@ -92,25 +113,18 @@ impl VersionChecker {
         match fs::read_to_string(path) {
             Ok(content) => match serde_json::from_str::<Vec<VersionInfo>>(&content) {
                 Ok(versions) => {
-                    #[cfg(debug_assertions)]
-                    println!("Debug: Loaded {} versions from cache", versions.len());
+                    debug!(count = versions.len(), "Loaded versions from cache");
                     versions
                 }
-                #[cfg(debug_assertions)]
                 Err(e) => {
-                    println!("Debug: Failed to parse cache file: {}", e);
+                    warn!(path = %self.cache_file_path, error = %e, "Failed to parse cache file");
                     Vec::new()
                 }
-                #[cfg(not(debug_assertions))]
-                Err(_) => Vec::new(),
             },
-            #[cfg(debug_assertions)]
             Err(e) => {
-                println!("Debug: Failed to read cache file: {}", e);
+                warn!(path = %self.cache_file_path, error = %e, "Failed to read cache file");
                 Vec::new()
             }
-            #[cfg(not(debug_assertions))]
-            Err(_) => Vec::new(),
         }
     }
 
@@ -124,14 +138,30 @@ impl VersionChecker {
         let json = serde_json::to_string_pretty(versions)?;
         fs::write(&self.cache_file_path, json)?;
 
-        #[cfg(debug_assertions)]
-        println!("Debug: Saved {} versions to cache", versions.len());
+        debug!(count = versions.len(), "Saved versions to cache");
 
         Ok(())
     }
 
+    async fn mark_updated(&self) {
+        self.updated_at.send_replace(SystemTime::now());
+    }
+
     async fn check_all_versions(
         &self,
+    ) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
+        let result = self.fetch_all_versions().await;
+        self.metrics.observe_version_check(result.is_ok());
+
+        if let Err(e) = &result {
+            warn!(package = %self.package_name, error = %e, "Version check failed");
+        }
+
+        result
+    }
+
+    async fn fetch_all_versions(
+        &self,
     ) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
         let url = format!(
             "https://data.jsdelivr.com/v1/package/npm/{}",
@@ -161,6 +191,21 @@ impl VersionChecker {
         }
 
         if let Some(tags) = json["tags"].as_object() {
+            let dist_tags: BTreeMap<String, String> = tags
+                .iter()
+                .filter_map(|(tag, version)| Some((tag.clone(), version.as_str()?.to_string())))
+                .collect();
+
+            let mut current_tags = self.dist_tags.write().await;
+            if *current_tags != dist_tags {
+                *current_tags = dist_tags;
+                drop(current_tags);
+
+                // Badges and version details show the tags
+                self.mark_updated().await;
+                debug!("Updated dist-tags");
+            }
+
             if let Some(latest) = tags.get("latest") {
                 if let Some(version) = latest.as_str() {
                     if !versions.contains(&version.to_string()) {
@@ -171,34 +216,129 @@ impl VersionChecker {
         }
 
         if versions.is_empty() {
-            #[cfg(debug_assertions)]
-            println!("Debug: No versions found in response: {:?}", json);
+            debug!(response = %json, "No versions found in response");
             return Err("No versions found in response".into());
         }
 
-        #[cfg(debug_assertions)]
-        println!("Debug: Found {} versions", versions.len());
+        debug!(count = versions.len(), "Found versions");
 
         Ok(versions)
     }
 
+    /// Reads publish times and deprecation notices from the npm registry,
+    /// which the jsDelivr API does not report.
+    async fn fetch_package_metadata(
+        &self,
+    ) -> Result<HashMap<String, VersionMetadata>, Box<dyn std::error::Error + Send + Sync>> {
+        let url = format!("https://registry.npmjs.org/{}", self.package_name);
+
+        let response = self
+            .client
+            .get(&url)
+            .send()
+            .await
+            .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?;
+
+        let json: Value = response
+            .json()
+            .await
+            .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?;
+
+        let mut metadata = HashMap::new();
+
+        if let Some(versions) = json["versions"].as_object() {
+            for (version, manifest) in versions {
+                metadata.insert(
+                    version.clone(),
+                    VersionMetadata {
+                        published: json["time"][version].as_str().map(str::to_string),
+                        deprecated: manifest["deprecated"].as_str().map(str::to_string),
+                    },
+                );
+            }
+        }
+
+        Ok(metadata)
+    }
+
+    /// Updates the publish times and deprecation notices of the cached
+    /// versions, saving the cache if any of them changed.
+    async fn refresh_metadata(&self) {
+        let metadata = match self.fetch_package_metadata().await {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                warn!(package = %self.package_name, error = %e, "Failed to fetch package metadata");
+                return;
+            }
+        };
+
+        let mut all = self.all_versions.write().await;
+        let mut changed = false;
+
+        for version_info in all.iter_mut() {
+            if let Some(entry) = metadata.get(&version_info.version) {
+                if version_info.published != entry.published
+                    || version_info.deprecated != entry.deprecated
+                {
+                    version_info.published = entry.published.clone();
+                    version_info.deprecated = entry.deprecated.clone();
+                    changed = true;
+                }
+            }
+        }
+
+        if changed {
+            if let Err(e) = self.save_cache(&all).await {
+                warn!(error = %e, "Failed to save cache");
+            }
+            drop(all);
+
+            self.mark_updated().await;
+            debug!("Updated version metadata");
+        }
+    }
+
     async fn calculate_sri_hash(
         &self,
         version: &str,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let result = self.download_sri_hash(version).await;
+
+        if let Err(e) = &result {
+            warn!(%version, error = %e, "Failed to calculate SRI hash");
+        }
+
+        result
+    }
+
+    async fn download_sri_hash(
+        &self,
+        version: &str,
     ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
         let url = format!(
             "https://cdn.jsdelivr.net/npm/{}@{}/dist/{}-min.js",
             self.package_name, version, self.package_name
         );
 
-        #[cfg(debug_assertions)]
-        println!("Debug: Downloading from {}", url);
+        let sri_hash = self.download_file_sri_hash(&url).await?;
+        debug!(%version, %sri_hash, "Calculated SRI hash");
 
+        Ok(sri_hash)
+    }
+
+    async fn download_file_sri_hash(
+        &self,
+        url: &str,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        debug!(%url, "Downloading file for SRI hash");
+
+        let start = Instant::now();
         let response = self
             .client
-            .get(&url)
+            .get(url)
             .send()
             .await
+            .and_then(|response| response.error_for_status())
             .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?;
 
         let bytes = response
@@ -206,18 +346,34 @@ impl VersionChecker {
             .await
             .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?;
 
+        self.metrics
+            .observe_sri_download(bytes.len(), start.elapsed());
+
         let mut hasher = Sha512::new();
         hasher.update(&bytes);
         let hash = hasher.finalize();
 
         let hash_base64 = BASE64.encode(hash);
-        let sri_hash = format!("sha512-{}", hash_base64);
+        Ok(format!("sha512-{}", hash_base64))
+    }
 
-        #[cfg(debug_assertions)]
-        println!(
-            "Debug: Calculated SRI hash for version {}: {}",
-            version, sri_hash
-        );
+    /// SRI hash of another file of a published version on the CDN, such as
+    /// a stylesheet, downloaded on first use. Those files never change.
+    pub async fn get_file_sri_hash(
+        &self,
+        url: &str,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(sri_hash) = self.file_sri_hashes.read().await.get(url) {
+            return Ok(sri_hash.clone());
+        }
+
+        let sri_hash = self.download_file_sri_hash(url).await.inspect_err(|e| {
+            warn!(%url, error = %e, "Failed to calculate SRI hash");
+        })?;
+        self.file_sri_hashes
+            .write()
+            .await
+            .insert(url.to_string(), sri_hash.clone());
 
         Ok(sri_hash)
     }
@@ -226,13 +382,7 @@ impl VersionChecker {
         let cached_versions = self.load_cache().await;
 
         if !cached_versions.is_empty() {
-            let latest_version = cached_versions
-                .first()
-                .cloned()
-                .unwrap_or_else(|| VersionInfo {
-                    version: String::new(),
-                    sri_hash: String::new(),
-                });
+            let latest_version = cached_versions.first().cloned().unwrap_or_default();
 
             {
                 let mut all = self.all_versions.write().await;
@@ -244,8 +394,7 @@ impl VersionChecker {
                 let mut latest = self.latest_version.write().await;
                 *latest = latest_version;
 
-                #[cfg(debug_assertions)]
-                println!("Debug: Initialized from cache with {} versions", all.len());
+                info!(count = all.len(), "Initialized from cache");
             }
         }
 
@@ -259,25 +408,29 @@ impl VersionChecker {
             let all_versions = all_versions.clone();
             let latest_version = latest_version.clone();
             let version_info = version_info.clone();
-            
+
             async move {
                 if let Ok(versions) = checker.check_all_versions().await {
                     let mut stored_versions = Vec::new();
-                    
+
                     let all = all_versions.read().await;
-                    let existing_versions: Vec<String> = all.iter().map(|v| v.version.clone()).collect();
-                    
+                    let existing_versions: Vec<String> =
+                        all.iter().map(|v| v.version.clone()).collect();
+
                     if !all.is_empty() {
                         stored_versions = all.clone();
                     }
                     drop(all);
-                    
+
                     if let Some(latest_version_str) = versions.first() {
                         if !existing_versions.contains(latest_version_str) {
-                            if let Ok(sri_hash) = checker.calculate_sri_hash(latest_version_str).await {
+                            if let Ok(sri_hash) =
+                                checker.calculate_sri_hash(latest_version_str).await
+                            {
                                 let latest_version_info = VersionInfo {
                                     version: latest_version_str.clone(),
                                     sri_hash: sri_hash.clone(),
+                                    ..Default::default()
                                 };
 
                                 let mut current = version_info.write().await;
@@ -287,15 +440,14 @@ impl VersionChecker {
                                 let mut latest = latest_version.write().await;
                                 *latest = latest_version_info.clone();
 
-                                if !stored_versions.iter().any(|v| v.version == latest_version_str.clone()) {
+                                if !stored_versions
+                                    .iter()
+                                    .any(|v| v.version == latest_version_str.clone())
+                                {
                                     stored_versions.insert(0, latest_version_info);
                                 }
 
-                                #[cfg(debug_assertions)]
-                                println!(
-                                    "Debug: Initial version set to {} with hash {}",
-                                    latest_version_str, sri_hash
-                                );
+                                info!(version = %latest_version_str, "Initial version set");
                             }
                         }
                     }
@@ -303,10 +455,14 @@ impl VersionChecker {
                     for version_str in versions.iter().skip(1).take(9) {
                         if !existing_versions.contains(version_str) {
                             if let Ok(sri_hash) = checker.calculate_sri_hash(version_str).await {
-                                if !stored_versions.iter().any(|v| v.version == version_str.clone()) {
+                                if !stored_versions
+                                    .iter()
+                                    .any(|v| v.version == version_str.clone())
+                                {
                                     stored_versions.push(VersionInfo {
                                         version: version_str.clone(),
                                         sri_hash,
+                                        ..Default::default()
                                     });
                                 }
                             }
@@ -316,7 +472,7 @@ impl VersionChecker {
                     if stored_versions.len() > existing_versions.len() {
                         let mut all = all_versions.write().await;
                         *all = stored_versions.clone();
-                        
+
                         all.sort_by(|a, b| {
                             let parse_version = |v: &str| -> (u32, u32, u32) {
                                 let parts: Vec<&str> = v.split('.').collect();
@@ -336,20 +492,18 @@ impl VersionChecker {
                             b_ver.cmp(&a_ver)
                         });
 
-                        #[cfg(debug_assertions)]
                         if let Err(e) = checker.save_cache(&all).await {
-                            println!("Debug: Failed to save cache: {}", e);
+                            warn!(error = %e, "Failed to save cache");
                         }
-                        #[cfg(not(debug_assertions))]
-                        let _ = checker.save_cache(&all).await;
+
+                        checker.mark_updated().await;
                     }
-                } else {
-                    #[cfg(debug_assertions)]
-                    println!("Debug: Failed to get initial versions");
+
+                    checker.refresh_metadata().await;
                 }
             }
         };
-        
+
         if cached_versions.is_empty() {
             check_versions.await;
         } else {
@@ -363,126 +517,113 @@ impl VersionChecker {
 
         tokio::spawn(async move {
             loop {
-                tokio::time::sleep(Duration::from_secs(checker_periodic.version_check_interval_secs)).await;
-
-                match checker_periodic.check_all_versions().await {
-                    Ok(versions) => {
-                        if let Some(new_version_str) = versions.first() {
-                            let current_latest = {
-                                let latest = latest_version_periodic.read().await;
-                                latest.version.clone()
-                            };
+                tokio::time::sleep(Duration::from_secs(
+                    checker_periodic.version_check_interval_secs,
+                ))
+                .await;
+
+                if let Ok(versions) = checker_periodic.check_all_versions().await {
+                    if let Some(new_version_str) = versions.first() {
+                        let current_latest = {
+                            let latest = latest_version_periodic.read().await;
+                            latest.version.clone()
+                        };
+
+                        if new_version_str != &current_latest {
+                            if let Ok(sri_hash) =
+                                checker_periodic.calculate_sri_hash(new_version_str).await
+                            {
+                                let new_version_info = VersionInfo {
+                                    version: new_version_str.clone(),
+                                    sri_hash: sri_hash.clone(),
+                                    ..Default::default()
+                                };
 
-                            if new_version_str != &current_latest {
-                                if let Ok(sri_hash) =
-                                    checker_periodic.calculate_sri_hash(new_version_str).await
-                                {
-                                    let new_version_info = VersionInfo {
-                                        version: new_version_str.clone(),
-                                        sri_hash: sri_hash.clone(),
-                                    };
-
-                                    let mut current = version_info_periodic.write().await;
-                                    current.version = new_version_str.clone();
-                                    current.sri_hash = sri_hash.clone();
-
-                                    let mut latest = latest_version_periodic.write().await;
-                                    *latest = new_version_info.clone();
-
-                                    let mut all = all_versions_periodic.write().await;
-                                    if !all.iter().any(|v| v.version == new_version_str.clone()) {
-                                        all.insert(0, new_version_info);
-
-                                        #[cfg(debug_assertions)]
-                                        if let Err(e) = checker_periodic.save_cache(&all).await {
-                                            println!("Debug: Failed to save cache: {}", e);
-                                        }
-                                        #[cfg(not(debug_assertions))]
-                                        let _ = checker_periodic.save_cache(&all).await;
-                                    }
+                                let mut current = version_info_periodic.write().await;
+                                current.version = new_version_str.clone();
+                                current.sri_hash = sri_hash.clone();
 
-                                    #[cfg(debug_assertions)]
-                                    println!(
-                                        "Debug: Updated version to {} with hash {}",
-                                        new_version_str, sri_hash
-                                    );
-                                } else {
-                                    #[cfg(debug_assertions)]
-                                    println!(
-                                        "Debug: Failed to calculate SRI hash for version {}",
-                                        new_version_str
-                                    );
-                                }
-                            }
+                                let mut latest = latest_version_periodic.write().await;
+                                *latest = new_version_info.clone();
 
-                            let mut new_versions_to_process = Vec::new();
-                            
-                            let all = all_versions_periodic.read().await;
-                            let existing_versions: Vec<String> = 
-                                all.iter().map(|v| v.version.clone()).collect();
+                                let mut all = all_versions_periodic.write().await;
+                                if !all.iter().any(|v| v.version == new_version_str.clone()) {
+                                    all.insert(0, new_version_info);
 
-                            for version_str in versions.iter().take(10) {
-                                if !existing_versions.contains(version_str) {
-                                    new_versions_to_process.push(version_str.clone());
+                                    if let Err(e) = checker_periodic.save_cache(&all).await {
+                                        warn!(error = %e, "Failed to save cache");
+                                    }
                                 }
+
+                                checker_periodic.mark_updated().await;
+
+                                info!(version = %new_version_str, "Updated latest version");
                             }
-                            drop(all);
+                        }
 
-                            if !new_versions_to_process.is_empty() {
-                                let mut all = all_versions_periodic.write().await;
-                                let mut cache_updated = false;
-
-                                for version_str in new_versions_to_process {
-                                    if !all.iter().any(|v| v.version == version_str) {
-                                        if let Ok(sri_hash) =
-                                            checker_periodic.calculate_sri_hash(&version_str).await
-                                        {
-                                            let version_info = VersionInfo {
-                                                version: version_str.clone(),
-                                                sri_hash,
-                                            };
-                                            all.push(version_info);
-                                            cache_updated = true;
-                                        }
+                        let mut new_versions_to_process = Vec::new();
+
+                        let all = all_versions_periodic.read().await;
+                        let existing_versions: Vec<String> =
+                            all.iter().map(|v| v.version.clone()).collect();
+
+                        for version_str in versions.iter().take(10) {
+                            if !existing_versions.contains(version_str) {
+                                new_versions_to_process.push(version_str.clone());
+                            }
+                        }
+                        drop(all);
+
+                        if !new_versions_to_process.is_empty() {
+                            let mut all = all_versions_periodic.write().await;
+                            let mut cache_updated = false;
+
+                            for version_str in new_versions_to_process {
+                                if !all.iter().any(|v| v.version == version_str) {
+                                    if let Ok(sri_hash) =
+                                        checker_periodic.calculate_sri_hash(&version_str).await
+                                    {
+                                        let version_info = VersionInfo {
+                                            version: version_str.clone(),
+                                            sri_hash,
+                                            ..Default::default()
+                                        };
+                                        all.push(version_info);
+                                        cache_updated = true;
                                     }
                                 }
+                            }
 
-                                all.sort_by(|a, b| {
-                                    let parse_version = |v: &str| -> (u32, u32, u32) {
-                                        let parts: Vec<&str> = v.split('.').collect();
-                                        if parts.len() == 3 {
-                                            (
-                                                parts[0].parse().unwrap_or(0),
-                                                parts[1].parse().unwrap_or(0),
-                                                parts[2].parse().unwrap_or(0),
-                                            )
-                                        } else {
-                                            (0, 0, 0)
-                                        }
-                                    };
-
-                                    let a_ver = parse_version(&a.version);
-                                    let b_ver = parse_version(&b.version);
-                                    b_ver.cmp(&a_ver)
-                                });
-
-                                if cache_updated {
-                                    #[cfg(debug_assertions)]
-                                    if let Err(e) = checker_periodic.save_cache(&all).await {
-                                        println!("Debug: Failed to save cache: {}", e);
+                            all.sort_by(|a, b| {
+                                let parse_version = |v: &str| -> (u32, u32, u32) {
+                                    let parts: Vec<&str> = v.split('.').collect();
+                                    if parts.len() == 3 {
+                                        (
+                                            parts[0].parse().unwrap_or(0),
+                                            parts[1].parse().unwrap_or(0),
+                                            parts[2].parse().unwrap_or(0),
+                                        )
+                                    } else {
+                                        (0, 0, 0)
                                     }
-                                    #[cfg(not(debug_assertions))]
-                                    let _ = checker_periodic.save_cache(&all).await;
+                                };
+
+                                let a_ver = parse_version(&a.version);
+                                let b_ver = parse_version(&b.version);
+                                b_ver.cmp(&a_ver)
+                            });
+
+                            if cache_updated {
+                                if let Err(e) = checker_periodic.save_cache(&all).await {
+                                    warn!(error = %e, "Failed to save cache");
                                 }
+
+                                checker_periodic.mark_updated().await;
                             }
                         }
                     }
-                    #[cfg(debug_assertions)]
-                    Err(e) => {
-                        println!("Debug: Error checking version: {}", e);
-                    }
-                    #[cfg(not(debug_assertions))]
-                    Err(_) => {}
+
+                    checker_periodic.refresh_metadata().await;
                 }
             }
         });
@@ -499,6 +640,76 @@ impl VersionChecker {
     pub async fn get_all_versions(&self) -> Vec<VersionInfo> {
         self.all_versions.read().await.clone()
     }
+
+    /// Whether at least one version with a computed SRI hash is available
+    pub async fn has_servable_version(&self) -> bool {
+        self.all_versions
+            .read()
+            .await
+            .iter()
+            .any(|v| !v.sri_hash.is_empty())
+    }
+
+    pub async fn get_dist_tags(&self) -> BTreeMap<String, String> {
+        self.dist_tags.read().await.clone()
+    }
+
+    /// Time of the last change to the cached version state
+    pub async fn get_updated_at(&self) -> SystemTime {
+        *self.updated_at.borrow()
+    }
+
+    /// Receiver that is notified whenever the cached version state changes
+    pub fn subscribe(&self) -> watch::Receiver<SystemTime> {
+        self.updated_at.subscribe()
+    }
+}
+
+#[cfg(test)]
+impl VersionChecker {
+    /// A checker holding `versions`, newest first, without fetching anything.
+    pub async fn with_versions(versions: Vec<VersionInfo>, dist_tags: &[(&str, &str)]) -> Self {
+        let checker = Self::new("highlight-it", 1, 3600, None, Metrics::new());
+
+        let latest = versions.first().cloned().unwrap_or_default();
+        *checker.current_version_info.write().await = latest.clone();
+        *checker.latest_version.write().await = latest;
+        *checker.all_versions.write().await = versions;
+        *checker.dist_tags.write().await = dist_tags
+            .iter()
+            .map(|(tag, version)| (tag.to_string(), version.to_string()))
+            .collect();
+
+        checker
+    }
+
+    /// Records the SRI hash of a CDN file so tests need not download it.
+    pub async fn insert_file_sri_hash(&self, url: &str, sri_hash: &str) {
+        self.file_sri_hashes
+            .write()
+            .await
+            .insert(url.to_string(), sri_hash.to_string());
+    }
+}
+
+/// Versions resembling the npm history of highlight-it, newest first.
+#[cfg(test)]
+pub fn sample_versions() -> Vec<VersionInfo> {
+    let version = |version: &str, published: Option<&str>, deprecated: Option<&str>| VersionInfo {
+        version: version.to_string(),
+        sri_hash: format!("sha512-{}", version),
+        published: published.map(str::to_string),
+        deprecated: deprecated.map(str::to_string),
+    };
+
+    vec![
+        version("1.3.0-beta.1", Some("2025-04-01T00:00:00Z"), None),
+        version("1.2.3", Some("2025-03-01T12:34:56Z"), None),
+        version("1.2.2", Some("2025-02-01T00:00:00Z"), Some("Use 1.2.3")),
+        version("1.1.0", Some("2025-01-01T00:00:00Z"), None),
+        version("0.3.7", Some("2024-06-01T00:00:00Z"), None),
+        version("0.2.1", None, None),
+    ]
 }
 
 impl Clone for VersionChecker {
@@ -509,6 +720,10 @@ impl Clone for VersionChecker {
             current_version_info: Arc::clone(&self.current_version_info),
             all_versions: Arc::clone(&self.all_versions),
             latest_version: Arc::clone(&self.latest_version),
+            dist_tags: Arc::clone(&self.dist_tags),
+            updated_at: Arc::clone(&self.updated_at),
+            file_sri_hashes: Arc::clone(&self.file_sri_hashes),
+            metrics: self.metrics.clone(),
             http_timeout_secs: self.http_timeout_secs,
             version_check_interval_secs: self.version_check_interval_secs,
             cache_file_path: self.cache_file_path.clone(),
@@ -530,19 +745,18 @@ pub fn get_versions_selector(
     latest_version: String,
     selected_version: Option<String>,
 ) -> String {
-    let mut versions_html = String::from(
-        "<select id=\"version-selector\" onchange=\"window.location.href='/' + this.value; document.getElementById('current-version-display').textContent = this.options[this.selectedIndex].text;\">",
-    );
+    let mut versions_html = String::from("<select id=\"version-selector\">");
 
     let latest_selected = selected_version.is_none()
         || selected_version
             .as_ref()
-            .map_or(false, |s| s == &latest_version);
+            .is_some_and(|s| s == &latest_version);
     let latest_selected_attr = if latest_selected { " selected" } else { "" };
 
     versions_html.push_str(&format!(
         "<option value=\"\"{}>Latest ({})</option>",
-        latest_selected_attr, latest_version
+        latest_selected_attr,
+        escape_html(&latest_version)
     ));
 
     for v in all_versions {
@@ -555,9 +769,10 @@ pub fn get_versions_selector(
             _ => "",
         };
 
+        let version = escape_html(&v.version);
         versions_html.push_str(&format!(
             "<option value=\"{}\"{}>{}</option>",
-            v.version, selected, v.version
+            version, selected, version
         ));
     }
 
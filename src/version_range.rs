@@ -0,0 +1,692 @@
+use std::cmp::Ordering;
+
+/// Longest range expression accepted
+pub const MAX_RANGE_LENGTH: usize = 256;
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Identifier {
+    Numeric(u64),
+    Alphanumeric(String),
+}
+
+impl Identifier {
+    fn parse(identifier: &str) -> Option<Self> {
+        if identifier.is_empty()
+            || !identifier
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-')
+        {
+            return None;
+        }
+
+        Some(match identifier.parse() {
+            Ok(number) => Identifier::Numeric(number),
+            Err(_) => Identifier::Alphanumeric(identifier.to_string()),
+        })
+    }
+}
+
+fn parse_prerelease(prerelease: &str) -> Option<Vec<Identifier>> {
+    prerelease.split('.').map(Identifier::parse).collect()
+}
+
+/// A semantic version as published on npm. Build metadata is ignored.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Version {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+    prerelease: Vec<Identifier>,
+}
+
+impl Version {
+    fn new(major: u64, minor: u64, patch: u64) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+            prerelease: Vec::new(),
+        }
+    }
+
+    /// The lowest possible prerelease of a version, used as an exclusive
+    /// upper bound so prereleases of the next version stay out of a range.
+    fn lowest(major: u64, minor: u64, patch: u64) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+            prerelease: vec![Identifier::Numeric(0)],
+        }
+    }
+
+    pub fn parse(version: &str) -> Option<Self> {
+        let version = version.trim();
+        let version = version.strip_prefix('v').unwrap_or(version);
+        let version = version.split('+').next()?;
+        let (release, prerelease) = match version.split_once('-') {
+            Some((release, prerelease)) => (release, parse_prerelease(prerelease)?),
+            None => (version, Vec::new()),
+        };
+
+        let mut parts = release.split('.');
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts.next()?.parse().ok()?;
+        let patch = parts.next()?.parse().ok()?;
+        if parts.next().is_some() {
+            return None;
+        }
+
+        Some(Self {
+            major,
+            minor,
+            patch,
+            prerelease,
+        })
+    }
+
+    pub fn is_prerelease(&self) -> bool {
+        !self.prerelease.is_empty()
+    }
+
+    fn same_release(&self, other: &Version) -> bool {
+        (self.major, self.minor, self.patch) == (other.major, other.minor, other.patch)
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.major, self.minor, self.patch)
+            .cmp(&(other.major, other.minor, other.patch))
+            .then_with(|| match (self.is_prerelease(), other.is_prerelease()) {
+                (false, false) => Ordering::Equal,
+                (false, true) => Ordering::Greater,
+                (true, false) => Ordering::Less,
+                (true, true) => self.prerelease.cmp(&other.prerelease),
+            })
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// A version with possibly missing parts, such as `1`, `1.2` or `1.x`.
+struct Partial {
+    major: Option<u64>,
+    minor: Option<u64>,
+    patch: Option<u64>,
+    prerelease: Vec<Identifier>,
+}
+
+impl Partial {
+    fn parse(partial: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid version: {}", partial);
+
+        let trimmed = partial.strip_prefix('v').unwrap_or(partial);
+        let trimmed = trimmed.split('+').next().unwrap_or_default();
+        let (release, prerelease) = match trimmed.split_once('-') {
+            Some((release, prerelease)) => {
+                (release, parse_prerelease(prerelease).ok_or_else(invalid)?)
+            }
+            None => (trimmed, Vec::new()),
+        };
+
+        let mut numbers = [None; 3];
+        let mut wildcard = false;
+        let parts: Vec<&str> = release.split('.').collect();
+        if parts.len() > 3 {
+            return Err(invalid());
+        }
+
+        for (number, part) in numbers.iter_mut().zip(&parts) {
+            if matches!(*part, "x" | "X" | "*") {
+                wildcard = true;
+            } else if !wildcard {
+                *number = Some(part.parse::<u64>().map_err(|_| invalid())?);
+            }
+        }
+
+        if !prerelease.is_empty() && numbers.iter().any(Option::is_none) {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            major: numbers[0],
+            minor: numbers[1],
+            patch: numbers[2],
+            prerelease,
+        })
+    }
+
+    /// The version with missing parts set to zero
+    fn floor(&self) -> Version {
+        Version {
+            major: self.major.unwrap_or(0),
+            minor: self.minor.unwrap_or(0),
+            patch: self.patch.unwrap_or(0),
+            prerelease: self.prerelease.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Operator {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equal,
+}
+
+#[derive(Clone, Debug)]
+struct Comparator {
+    operator: Operator,
+    version: Version,
+}
+
+impl Comparator {
+    fn new(operator: Operator, version: Version) -> Self {
+        Self { operator, version }
+    }
+
+    fn any() -> Self {
+        Self::new(Operator::GreaterOrEqual, Version::new(0, 0, 0))
+    }
+
+    fn none() -> Self {
+        Self::new(Operator::Less, Version::lowest(0, 0, 0))
+    }
+
+    fn matches(&self, version: &Version) -> bool {
+        let ordering = version.cmp(&self.version);
+        match self.operator {
+            Operator::Less => ordering == Ordering::Less,
+            Operator::LessOrEqual => ordering != Ordering::Greater,
+            Operator::Greater => ordering == Ordering::Greater,
+            Operator::GreaterOrEqual => ordering != Ordering::Less,
+            Operator::Equal => ordering == Ordering::Equal,
+        }
+    }
+}
+
+/// `number + 1` for an exclusive upper bound, which a range naming the
+/// largest number cannot have.
+fn increment(number: u64) -> Result<u64, String> {
+    number
+        .checked_add(1)
+        .ok_or_else(|| format!("Version number {} is too large", number))
+}
+
+/// Comparators for `=1`, `1.2`, `1.x` and the like.
+fn x_range(operator: Operator, partial: &Partial) -> Result<Vec<Comparator>, String> {
+    use Operator::*;
+
+    let (Some(major), minor, patch) = (partial.major, partial.minor, partial.patch) else {
+        return Ok(match operator {
+            Less | Greater => vec![Comparator::none()],
+            _ => vec![Comparator::any()],
+        });
+    };
+
+    Ok(match (operator, minor, patch) {
+        (Equal, None, _) => vec![
+            Comparator::new(GreaterOrEqual, Version::new(major, 0, 0)),
+            Comparator::new(Less, Version::lowest(increment(major)?, 0, 0)),
+        ],
+        (Equal, Some(minor), None) => vec![
+            Comparator::new(GreaterOrEqual, Version::new(major, minor, 0)),
+            Comparator::new(Less, Version::lowest(major, increment(minor)?, 0)),
+        ],
+        (Greater, None, _) => vec![Comparator::new(
+            GreaterOrEqual,
+            Version::new(increment(major)?, 0, 0),
+        )],
+        (Greater, Some(minor), None) => vec![Comparator::new(
+            GreaterOrEqual,
+            Version::new(major, increment(minor)?, 0),
+        )],
+        (Less, None, _) => vec![Comparator::new(Less, Version::lowest(major, 0, 0))],
+        (Less, Some(minor), None) => vec![Comparator::new(Less, Version::lowest(major, minor, 0))],
+        (LessOrEqual, None, _) => vec![Comparator::new(
+            Less,
+            Version::lowest(increment(major)?, 0, 0),
+        )],
+        (LessOrEqual, Some(minor), None) => {
+            vec![Comparator::new(
+                Less,
+                Version::lowest(major, increment(minor)?, 0),
+            )]
+        }
+        (GreaterOrEqual, _, _) | (_, Some(_), Some(_)) => {
+            vec![Comparator::new(operator, partial.floor())]
+        }
+    })
+}
+
+/// Comparators for `~1.2.3`: patch updates, or minor updates if only the
+/// major version is given.
+fn tilde(partial: &Partial) -> Result<Vec<Comparator>, String> {
+    let Some(major) = partial.major else {
+        return Ok(vec![Comparator::any()]);
+    };
+
+    let upper = match partial.minor {
+        Some(minor) => Version::lowest(major, increment(minor)?, 0),
+        None => Version::lowest(increment(major)?, 0, 0),
+    };
+
+    Ok(vec![
+        Comparator::new(Operator::GreaterOrEqual, partial.floor()),
+        Comparator::new(Operator::Less, upper),
+    ])
+}
+
+/// Comparators for `^1.2.3`: updates that keep the leftmost non-zero part.
+fn caret(partial: &Partial) -> Result<Vec<Comparator>, String> {
+    let Some(major) = partial.major else {
+        return Ok(vec![Comparator::any()]);
+    };
+
+    let upper = match (major, partial.minor, partial.patch) {
+        (_, None, _) => Version::lowest(increment(major)?, 0, 0),
+        (0, Some(minor), None) => Version::lowest(0, increment(minor)?, 0),
+        (0, Some(0), Some(patch)) => Version::lowest(0, 0, increment(patch)?),
+        (0, Some(minor), Some(_)) => Version::lowest(0, increment(minor)?, 0),
+        _ => Version::lowest(increment(major)?, 0, 0),
+    };
+
+    Ok(vec![
+        Comparator::new(Operator::GreaterOrEqual, partial.floor()),
+        Comparator::new(Operator::Less, upper),
+    ])
+}
+
+/// Comparators for `1.2.3 - 2.3`, inclusive on both ends.
+fn hyphen(lower: &Partial, upper: &Partial) -> Result<Vec<Comparator>, String> {
+    let mut comparators = vec![Comparator::new(Operator::GreaterOrEqual, lower.floor())];
+
+    if let Some(major) = upper.major {
+        comparators.push(match (upper.minor, upper.patch) {
+            (None, _) => Comparator::new(Operator::Less, Version::lowest(increment(major)?, 0, 0)),
+            (Some(minor), None) => {
+                Comparator::new(Operator::Less, Version::lowest(major, increment(minor)?, 0))
+            }
+            (Some(_), Some(_)) => Comparator::new(Operator::LessOrEqual, upper.floor()),
+        });
+    }
+
+    Ok(comparators)
+}
+
+const OPERATORS: [&str; 8] = [">=", "<=", "~>", ">", "<", "=", "~", "^"];
+
+fn parse_comparators(token: &str) -> Result<Vec<Comparator>, String> {
+    let operator = OPERATORS
+        .iter()
+        .find(|operator| token.starts_with(**operator))
+        .copied()
+        .unwrap_or("");
+    let partial = Partial::parse(token[operator.len()..].trim())?;
+
+    match operator {
+        "~" | "~>" => tilde(&partial),
+        "^" => caret(&partial),
+        ">=" => x_range(Operator::GreaterOrEqual, &partial),
+        "<=" => x_range(Operator::LessOrEqual, &partial),
+        ">" => x_range(Operator::Greater, &partial),
+        "<" => x_range(Operator::Less, &partial),
+        _ => x_range(Operator::Equal, &partial),
+    }
+}
+
+/// Splits a range into comparator tokens, joining operators written apart
+/// from their version as in `>= 1.2.3`.
+fn tokens(range: &str) -> Vec<String> {
+    let mut tokens: Vec<String> = Vec::new();
+    let mut pending = String::new();
+
+    for word in range.split_whitespace() {
+        pending.push_str(word);
+        if !OPERATORS.contains(&pending.as_str()) {
+            tokens.push(std::mem::take(&mut pending));
+        }
+    }
+    if !pending.is_empty() {
+        tokens.push(pending);
+    }
+
+    tokens
+}
+
+/// An npm version range such as `^0.3`, `~1.2.3 || >=2.0.0 <2.1` or
+/// `1.0 - 1.4`, evaluated with the rules of node-semver.
+#[derive(Clone, Debug)]
+pub struct VersionRange {
+    /// Alternatives separated by `||`, each matching if all of its
+    /// comparators do
+    sets: Vec<Vec<Comparator>>,
+}
+
+impl VersionRange {
+    pub fn parse(range: &str) -> Result<Self, String> {
+        if range.len() > MAX_RANGE_LENGTH {
+            return Err(format!(
+                "Range is longer than {} characters",
+                MAX_RANGE_LENGTH
+            ));
+        }
+
+        let mut sets = Vec::new();
+
+        for alternative in range.split("||") {
+            let alternative = alternative.trim();
+
+            let set = if let Some((lower, upper)) = alternative.split_once(" - ") {
+                hyphen(
+                    &Partial::parse(lower.trim())?,
+                    &Partial::parse(upper.trim())?,
+                )?
+            } else if alternative.is_empty() {
+                vec![Comparator::any()]
+            } else {
+                let mut set = Vec::new();
+                for token in tokens(alternative) {
+                    set.extend(parse_comparators(&token)?);
+                }
+                set
+            };
+
+            sets.push(set);
+        }
+
+        Ok(Self { sets })
+    }
+
+    /// Whether `version` is in the range. Prereleases only match a set that
+    /// names a prerelease of the same version, unless `include_prerelease`.
+    pub fn matches(&self, version: &Version, include_prerelease: bool) -> bool {
+        self.sets.iter().any(|set| {
+            set.iter().all(|comparator| comparator.matches(version))
+                && (!version.is_prerelease()
+                    || include_prerelease
+                    || set.iter().any(|comparator| {
+                        comparator.version.is_prerelease()
+                            && comparator.version.same_release(version)
+                    }))
+        })
+    }
+
+    /// How far `version` is from the lower bound of the nearest alternative
+    /// of the range, for suggesting alternatives. Majors are compared first,
+    /// and minors only within the same major. Upper bounds are left out, as
+    /// `^5.2` stops before `6.0.0-0` and does not reach 6.x.
+    pub fn distance(&self, version: &Version) -> (u64, u64) {
+        let lowest = Version::new(0, 0, 0);
+
+        self.sets
+            .iter()
+            .flat_map(|set| {
+                let lower_bounds: Vec<&Version> = set
+                    .iter()
+                    .filter(|comparator| {
+                        matches!(
+                            comparator.operator,
+                            Operator::Greater | Operator::GreaterOrEqual | Operator::Equal
+                        )
+                    })
+                    .map(|comparator| &comparator.version)
+                    .collect();
+
+                if lower_bounds.is_empty() {
+                    vec![&lowest]
+                } else {
+                    lower_bounds
+                }
+            })
+            .map(|bound| {
+                let major = bound.major.abs_diff(version.major);
+                let minor = if major == 0 {
+                    bound.minor.abs_diff(version.minor)
+                } else {
+                    0
+                };
+                (major, minor)
+            })
+            .min()
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(version: &str) -> Version {
+        Version::parse(version).unwrap()
+    }
+
+    #[test]
+    fn parses_versions() {
+        assert_eq!(version("1.2.3"), Version::new(1, 2, 3));
+        assert_eq!(version("v1.2.3"), Version::new(1, 2, 3));
+        assert_eq!(version(" 1.2.3+build.5 "), Version::new(1, 2, 3));
+        assert!(version("1.3.0-beta.1").is_prerelease());
+
+        for invalid in [
+            "",
+            "1.2",
+            "1.2.3.4",
+            "1.2.x",
+            "a.b.c",
+            "1.2.3-",
+            "1.2.3-beta..1",
+        ] {
+            assert_eq!(Version::parse(invalid), None, "{}", invalid);
+        }
+        assert_eq!(Version::parse("18446744073709551616.0.0"), None);
+    }
+
+    #[test]
+    fn orders_prereleases() {
+        let ordered = [
+            "1.0.0-alpha",
+            "1.0.0-alpha.1",
+            "1.0.0-alpha.beta",
+            "1.0.0-beta",
+            "1.0.0-beta.2",
+            "1.0.0-beta.11",
+            "1.0.0-rc.1",
+            "1.0.0",
+            "1.0.1",
+            "1.1.0",
+            "2.0.0",
+        ];
+
+        for pair in ordered.windows(2) {
+            assert!(
+                version(pair[0]) < version(pair[1]),
+                "{} < {}",
+                pair[0],
+                pair[1]
+            );
+        }
+    }
+
+    #[test]
+    fn matches_ranges() {
+        let cases = [
+            ("^1.2.3", "1.2.3", true),
+            ("^1.2.3", "1.9.0", true),
+            ("^1.2.3", "1.2.2", false),
+            ("^1.2.3", "2.0.0", false),
+            ("^0.2.3", "0.2.9", true),
+            ("^0.2.3", "0.3.0", false),
+            ("^0.0.3", "0.0.3", true),
+            ("^0.0.3", "0.0.4", false),
+            ("^0.3", "0.3.9", true),
+            ("^0.3", "0.4.0", false),
+            ("^1", "1.9.9", true),
+            ("^1", "2.0.0", false),
+            ("~1.2.3", "1.2.9", true),
+            ("~1.2.3", "1.3.0", false),
+            ("~>1.2", "1.2.0", true),
+            ("~>1.2", "1.3.0", false),
+            ("~1", "1.9.0", true),
+            ("~1", "2.0.0", false),
+            ("1.x", "1.5.0", true),
+            ("1.x", "2.0.0", false),
+            ("1.2.*", "1.2.5", true),
+            ("1.2.*", "1.3.0", false),
+            ("*", "3.0.0", true),
+            ("", "3.0.0", true),
+            ("=1.2.3", "1.2.3", true),
+            ("=1.2.3", "1.2.4", false),
+            ("v1.2.3", "1.2.3", true),
+            (">=1.2.3", "1.2.3", true),
+            (">=1.2.3", "1.2.2", false),
+            (">1.2.3", "1.2.3", false),
+            (">1.2.3", "1.2.4", true),
+            (">1", "1.9.9", false),
+            (">1", "2.0.0", true),
+            (">1.2", "1.2.9", false),
+            (">1.2", "1.3.0", true),
+            ("<1.2.3", "1.2.2", true),
+            ("<1.2.3", "1.2.3", false),
+            ("<1", "0.9.9", true),
+            ("<1", "1.0.0", false),
+            ("<1.2", "1.1.9", true),
+            ("<1.2", "1.2.0", false),
+            ("<=1.2.3", "1.2.3", true),
+            ("<=1.2", "1.2.9", true),
+            ("<=1.2", "1.3.0", false),
+            ("<=1", "1.9.9", true),
+            ("<=1", "2.0.0", false),
+            (">*", "1.0.0", false),
+            ("<*", "1.0.0", false),
+            (">= 1.2.3 < 1.3", "1.2.9", true),
+            (">= 1.2.3 < 1.3", "1.3.0", false),
+            ("1.0 - 1.4", "1.0.0", true),
+            ("1.0 - 1.4", "1.4.9", true),
+            ("1.0 - 1.4", "1.5.0", false),
+            ("1.0.0 - 1.4.2", "1.4.2", true),
+            ("1.0.0 - 1.4.2", "1.4.3", false),
+            ("1 - 2", "2.9.9", true),
+            ("1 - 2", "3.0.0", false),
+            ("~1.2.3 || >=2.0.0 <2.1", "1.2.5", true),
+            ("~1.2.3 || >=2.0.0 <2.1", "2.0.5", true),
+            ("~1.2.3 || >=2.0.0 <2.1", "2.1.0", false),
+            ("~1.2.3 || >=2.0.0 <2.1", "1.5.0", false),
+            ("^1.2.0", "1.3.0-beta.1", false),
+            ("^1.2.0", "2.0.0-0", false),
+            ("<2", "2.0.0-beta.1", false),
+            ("^1.3.0-beta.1", "1.3.0-beta.2", true),
+            ("^1.3.0-beta.1", "1.3.0", true),
+            ("^1.3.0-beta.1", "1.3.0-alpha", false),
+            ("^1.3.0-beta.1", "1.4.0-beta.1", false),
+            ("^18446744073709551614", "18446744073709551614.5.0", true),
+            (">=18446744073709551615", "18446744073709551615.0.0", true),
+        ];
+
+        for (range, candidate, expected) in cases {
+            let parsed = VersionRange::parse(range).unwrap();
+            assert_eq!(
+                parsed.matches(&version(candidate), false),
+                expected,
+                "{} matches {}",
+                range,
+                candidate
+            );
+        }
+    }
+
+    #[test]
+    fn includes_prereleases_on_request() {
+        let range = VersionRange::parse("^1.2.0").unwrap();
+
+        assert!(range.matches(&version("1.3.0-beta.1"), true));
+        assert!(!range.matches(&version("2.0.0-beta.1"), true));
+    }
+
+    #[test]
+    fn rejects_invalid_ranges() {
+        for range in [
+            "abc",
+            ">>1",
+            "1.2.3.4",
+            "^1.2.3-",
+            "1.x.3-beta",
+            "18446744073709551616",
+            "1.0 - ",
+        ] {
+            assert!(VersionRange::parse(range).is_err(), "{}", range);
+        }
+
+        assert!(VersionRange::parse(&"1".repeat(MAX_RANGE_LENGTH + 1)).is_err());
+    }
+
+    #[test]
+    fn rejects_bounds_past_the_largest_number() {
+        for range in [
+            "~18446744073709551615",
+            "~1.18446744073709551615",
+            "^18446744073709551615",
+            "^18446744073709551615.0.0",
+            "^0.18446744073709551615",
+            "^0.0.18446744073709551615",
+            "18446744073709551615",
+            "1.18446744073709551615",
+            ">18446744073709551615",
+            ">1.18446744073709551615",
+            "<=18446744073709551615",
+            "<=1.18446744073709551615",
+            "1 - 18446744073709551615",
+            "1 - 1.18446744073709551615",
+            "^1 || ~18446744073709551615",
+        ] {
+            let error = VersionRange::parse(range).unwrap_err();
+            assert!(error.contains("too large"), "{}: {}", range, error);
+        }
+
+        for range in [
+            "~18446744073709551615.0",
+            "18446744073709551615.0.0",
+            "1 - 18446744073709551615.0.0",
+            "<18446744073709551615",
+        ] {
+            assert!(VersionRange::parse(range).is_ok(), "{}", range);
+        }
+    }
+
+    #[test]
+    fn measures_distance_to_the_range() {
+        let range = VersionRange::parse("^5.2").unwrap();
+
+        assert_eq!(range.distance(&version("5.0.0")), (0, 2));
+        assert_eq!(range.distance(&version("1.2.3")), (4, 0));
+        assert_eq!(range.distance(&version("6.9.0")), (1, 0));
+        assert!(range.distance(&version("5.1.0")) < range.distance(&version("6.0.0")));
+
+        for (range, candidate, distance) in [
+            ("~1.4.2", "1.9.0", (0, 5)),
+            (">=2.1.0 <3", "3.0.0", (1, 0)),
+            ("<2", "4.1.0", (4, 0)),
+            ("1.x || >=4.2", "4.0.0", (0, 2)),
+            ("1.x || >=4.2", "2.5.0", (1, 0)),
+            ("=3.1.4", "3.0.0", (0, 1)),
+            ("1.2 - 1.4", "2.0.0", (1, 0)),
+        ] {
+            assert_eq!(
+                VersionRange::parse(range)
+                    .unwrap()
+                    .distance(&version(candidate)),
+                distance,
+                "{} {}",
+                range,
+                candidate
+            );
+        }
+    }
+}
@@ -0,0 +1,180 @@
+document.addEventListener('DOMContentLoaded', function() {
+    const container = document.getElementById('api-operations');
+
+    fetch('/api/openapi.json')
+        .then(response => {
+            if (!response.ok) {
+                throw new Error(`HTTP ${response.status}`);
+            }
+            return response.json();
+        })
+        .then(spec => renderSpec(spec))
+        .catch(err => {
+            container.replaceChildren(element('p', 'api-muted', `Could not load the API description: ${err.message}`));
+        });
+
+    function element(tag, className, text) {
+        const node = document.createElement(tag);
+        if (className) {
+            node.className = className;
+        }
+        if (text !== undefined) {
+            node.textContent = text;
+        }
+        return node;
+    }
+
+    function resolve(spec, schema) {
+        if (schema && schema.$ref) {
+            const name = schema.$ref.split('/').pop();
+            return { name, schema: spec.components.schemas[name] || {} };
+        }
+        return { name: null, schema: schema || {} };
+    }
+
+    function typeName(spec, schema) {
+        const resolved = resolve(spec, schema);
+        if (resolved.name) {
+            return resolved.name;
+        }
+        if (resolved.schema.type === 'array') {
+            return `${typeName(spec, resolved.schema.items)}[]`;
+        }
+        if (Array.isArray(resolved.schema.type)) {
+            return resolved.schema.type.join(' | ');
+        }
+        if (resolved.schema.oneOf) {
+            return resolved.schema.oneOf.map(variant => typeName(spec, variant)).join(' | ');
+        }
+        return resolved.schema.type || 'any';
+    }
+
+    function renderSchema(spec, schema, seen) {
+        const resolved = resolve(spec, schema);
+        let target = resolved.schema;
+        if (target.type === 'array') {
+            return renderSchema(spec, target.items, seen);
+        }
+        if (resolved.name) {
+            if (seen.includes(resolved.name)) {
+                return null;
+            }
+            seen = seen.concat(resolved.name);
+        }
+        if (!target.properties) {
+            return null;
+        }
+
+        const list = element('div', 'api-schema');
+        const required = target.required || [];
+        Object.entries(target.properties).forEach(([name, property]) => {
+            const row = element('div');
+            row.appendChild(element('code', null, name));
+            row.appendChild(document.createTextNode(' '));
+            row.appendChild(element('span', 'api-type', typeName(spec, property)));
+            if (!required.includes(name)) {
+                row.appendChild(element('span', 'api-muted', ' (optional)'));
+            }
+            if (property.description) {
+                row.appendChild(document.createTextNode(` — ${property.description}`));
+            }
+            const nested = renderSchema(spec, property, seen);
+            if (nested) {
+                row.appendChild(nested);
+            }
+            list.appendChild(row);
+        });
+        return list;
+    }
+
+    function renderParameters(parameters) {
+        const table = element('table');
+        const header = element('tr');
+        ['Parameter', 'In', 'Type', 'Description'].forEach(title => header.appendChild(element('th', null, title)));
+        table.appendChild(header);
+
+        parameters.forEach(parameter => {
+            const row = element('tr');
+            const name = element('td');
+            name.appendChild(element('code', null, parameter.name));
+            if (parameter.required) {
+                name.appendChild(element('span', 'api-muted', ' required'));
+            }
+            row.appendChild(name);
+            row.appendChild(element('td', null, parameter.in));
+            row.appendChild(element('td', 'api-type', parameter.schema ? parameter.schema.type || '' : ''));
+            row.appendChild(element('td', null, parameter.description || ''));
+            table.appendChild(row);
+        });
+        return table;
+    }
+
+    function renderOperation(spec, path, method, operation) {
+        const card = element('section', 'api-operation');
+        const title = element('h3');
+        title.appendChild(element('span', 'api-method', method));
+        title.appendChild(element('code', null, path));
+        card.appendChild(title);
+
+        if (operation.summary) {
+            card.appendChild(element('p', null, operation.summary));
+        }
+        if (operation.description && operation.description !== operation.summary) {
+            card.appendChild(element('p', 'api-muted', operation.description));
+        }
+        if (operation.parameters && operation.parameters.length > 0) {
+            card.appendChild(renderParameters(operation.parameters));
+        }
+
+        Object.entries(operation.responses || {}).forEach(([status, response]) => {
+            const block = element('div');
+            const line = element('p');
+            line.appendChild(element('strong', null, status));
+            line.appendChild(document.createTextNode(` ${response.description || ''}`));
+
+            const media = response.content && Object.values(response.content)[0];
+            if (media && media.schema) {
+                line.appendChild(document.createTextNode(' '));
+                line.appendChild(element('span', 'api-type', typeName(spec, media.schema)));
+            }
+            block.appendChild(line);
+
+            const schema = media && media.schema ? renderSchema(spec, media.schema, []) : null;
+            if (schema) {
+                block.appendChild(schema);
+            }
+            card.appendChild(block);
+        });
+
+        return card;
+    }
+
+    function renderSpec(spec) {
+        if (spec.info) {
+            document.getElementById('api-title').textContent = `${spec.info.title} ${spec.info.version || ''}`;
+            document.getElementById('api-description').textContent = spec.info.description || '';
+        }
+
+        const byTag = new Map();
+        Object.entries(spec.paths || {}).forEach(([path, item]) => {
+            Object.entries(item).forEach(([method, operation]) => {
+                const tag = (operation.tags && operation.tags[0]) || 'default';
+                if (!byTag.has(tag)) {
+                    byTag.set(tag, []);
+                }
+                byTag.get(tag).push(renderOperation(spec, path, method, operation));
+            });
+        });
+
+        const tagDescriptions = new Map((spec.tags || []).map(tag => [tag.name, tag.description]));
+        container.replaceChildren();
+        byTag.forEach((operations, tag) => {
+            const heading = element('h2', 'api-tag', tag);
+            container.appendChild(heading);
+            if (tagDescriptions.get(tag)) {
+                container.appendChild(element('p', 'api-muted', tagDescriptions.get(tag)));
+            }
+            operations.forEach(operation => container.appendChild(operation));
+        });
+    }
+});
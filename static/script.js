@@ -183,6 +183,17 @@ document.addEventListener('DOMContentLoaded', () => {
     styleSelector.addEventListener('change', function() {
         loadHighlightStyle(this.value);
     });
+
+    document.querySelectorAll('#version-selector').forEach((versionSelector) => {
+        versionSelector.addEventListener('change', function() {
+            window.location.href = '/' + this.value;
+
+            const currentVersionDisplay = document.getElementById('current-version-display');
+            if (currentVersionDisplay) {
+                currentVersionDisplay.textContent = this.options[this.selectedIndex].text;
+            }
+        });
+    });
     
     const existingStyleLink = document.getElementById('highlight-style');
     if (existingStyleLink) {